@@ -0,0 +1,65 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use drogue_es_wifi::adapter::{Adapter, AdapterConfig, ScanResult, Station};
+use drogue_es_wifi::mock::{MockClock, MockModule};
+use drogue_network::addr::{HostAddr, HostSocketAddr, IpAddr, Ipv4Addr};
+use drogue_network::dns::{AddrType, Dns};
+use drogue_network::tcp::{Mode, TcpStack};
+use heapless::{consts::*, Vec};
+
+/// Commands whose responses the adapter parses, in the order the input's
+/// 0xFF-separated chunks are assigned to them.
+const COMMANDS: &[&str] = &["C0", "P6=1", "S0", "R0", "P6=0", "D0", "F0", "C?", "CR", "Z5", "AR", "MR", "P?"];
+
+// The adapter must report an error, never panic, whatever the module answers.
+fuzz_target!(|data: &[u8]| {
+    let module = MockModule::new();
+    let clock = MockClock::new();
+
+    for (command, response) in COMMANDS.iter().zip(data.split(|b| *b == 0xFF)) {
+        if response.len() > 2048 {
+            return;
+        }
+        module.respond(command, response);
+    }
+
+    let mut adapter = match Adapter::new(
+        module.spi(),
+        module.cs(),
+        module.ready(),
+        module.wakeup(),
+        module.reset(),
+        &clock,
+        AdapterConfig::default(),
+    ) {
+        Ok(adapter) => adapter,
+        Err(_) => return,
+    };
+    if adapter.init().is_err() {
+        return;
+    }
+
+    let _ = adapter.join_wpa2("drogue", "rodneygnome");
+
+    let remote = HostSocketAddr::new(HostAddr::new(IpAddr::V4(Ipv4Addr::new(192, 168, 1, 245)), None), 8080);
+    if let Ok(socket) = adapter.open(Mode::NonBlocking) {
+        if let Ok(mut socket) = adapter.connect(socket, remote) {
+            let _ = adapter.write(&mut socket, b"hello, world");
+            let mut buffer = [0; 64];
+            let _ = adapter.read(&mut socket, &mut buffer);
+            let _ = adapter.close(socket);
+        }
+    }
+
+    let _ = adapter.gethostbyname("drogue.io", AddrType::IPv4);
+
+    let mut results: Vec<ScanResult, U8> = Vec::new();
+    let _ = adapter.scan(&mut results);
+    let mut stations: Vec<Station, U4> = Vec::new();
+    let _ = adapter.access_point_stations(&mut stations);
+    let _ = adapter.network_status();
+    let _ = adapter.estimate_channel();
+
+    while adapter.poll_event().is_some() {}
+});
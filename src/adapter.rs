@@ -1,92 +1,468 @@
 use heapless::{
+    ArrayLength,
     String,
     Vec,
-    spsc::{Producer, Consumer},
+    spsc::Queue,
     consts::*,
 };
 use crate::socket::{Socket, State};
-//use crate::network::EsWifiNetworkDriver;
-use drogue_network::addr::HostSocketAddr;
-use crate::arbiter::{Arbiter, SpiError};
+use drogue_network::addr::{IpAddr, Ipv4Addr, SocketAddr};
+use drogue_network::dns::DnsError;
+use drogue_network::tcp::{TcpError, TcpImplError};
+use crate::arbiter::Arbiter;
 use core::cell::RefCell;
-use drogue_embedded_timer::Delay;
+use core::fmt::Debug;
 use embedded_hal::blocking::spi::Transfer;
 use embedded_hal::digital::v2::{OutputPin, InputPin};
-use crate::parser::join;
 
-pub enum AdapterError {
-    ReadError,
+/// Errors reported by the adapter.
+///
+/// `SPI` is the error type of the HAL's SPI transfer, and `PIN` that of the
+/// chip-select pin. The ready, wake-up and reset pins may each have their own
+/// error type, so a failure of one of them only records which pin failed.
+/// Where the module rejected a request, its own explanation is kept in a `ModuleError`.
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error<SPI, PIN> {
+    /// The SPI transfer failed.
+    Spi(SPI),
+    /// Driving the chip-select pin failed.
+    Pin(PIN),
+    /// Driving or sensing one of the other control pins failed.
+    ControlPin(ControlPin),
+    /// A socket's `Mode::Timeout` expired.
+    Timeout,
+    /// The module stopped responding, and will be reset. Every socket is
+    /// lost, and an `Event::Reset` is queued.
+    Unresponsive,
+    /// The module failed to initialize, and `Adapter::init()` must be called again.
+    NotReady,
+    /// The module never signalled ready after being reset.
+    NoReadySignal,
+    /// The module did not answer with its prompt.
+    BadBanner,
+    /// The module answered with something that could not be parsed.
+    UnexpectedResponse,
+    /// A response did not fit in the adapter's `BUFFER`. What did fit of a
+    /// scan is still returned.
+    ResponseTruncated,
+    /// The module rejected a request.
+    Module(ModuleError),
+    InvalidSsid,
+    InvalidPassword,
+    InvalidIpConfig,
+    InvalidChannel,
+    InvalidMaxClients,
+    InvalidHostname,
+    /// The module could not join the access point.
+    UnableToAssociate(ModuleError),
     NoAvailableSockets,
     SocketNotOpen,
+    ConnectionFailed(ModuleError),
+    TlsHandshakeFailed(ModuleError),
+    WriteFailed(ModuleError),
+    ReadFailed(ModuleError),
+    /// A UDP datagram must be written in one piece, of at most 1046 bytes.
+    DatagramTooLarge,
+    /// The module can't send an empty UDP datagram.
+    EmptyDatagram,
+    EmptyCertificate,
+    UnsupportedAddressType,
+    NoSuchHost(ModuleError),
+    /// The module's resolver did not get an answer in time.
+    DnsTimeout,
 }
 
+/// A control pin other than chip-select, from `Error::ControlPin`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ControlPin {
+    Ready,
+    Wakeup,
+    Reset,
+}
 
-#[derive(Debug)]
-pub enum JoinError {
-    Unknown,
-    InvalidSsid,
-    InvalidPassword,
-    UnableToAssociate,
+/// What the module printed along with a failure, such as `-1` for a failed
+/// write, or the reason a connection was refused.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ModuleError {
+    pub(crate) message: String<U64>,
+}
+
+impl ModuleError {
+    /// The module's messages, joined by `; ` and truncated to 64 characters.
+    pub fn message(&self) -> &str {
+        self.message.as_str()
+    }
+
+    /// The numeric code reported by the module, if the message starts with one.
+    pub fn code(&self) -> Option<i32> {
+        let code = self.message.split(';').next()?;
+        code.trim().parse().ok()
+    }
 }
 
+#[cfg(feature = "defmt")]
+impl defmt::Format for ModuleError {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(f, "{=str}", self.message())
+    }
+}
+
+impl<SPI, PIN> From<Error<SPI, PIN>> for TcpError {
+    fn from(e: Error<SPI, PIN>) -> Self {
+        match e {
+            Error::NoAvailableSockets => TcpError::NoAvailableSockets,
+            Error::SocketNotOpen => TcpError::SocketNotOpen,
+            Error::ConnectionFailed(_) | Error::TlsHandshakeFailed(_) => TcpError::ConnectionRefused,
+            Error::WriteFailed(_) | Error::DatagramTooLarge | Error::EmptyDatagram => TcpError::WriteError,
+            Error::ReadFailed(_) => TcpError::ReadError,
+            Error::Timeout | Error::Unresponsive | Error::DnsTimeout => TcpError::Timeout,
+            Error::NotReady | Error::NoReadySignal | Error::BadBanner => TcpError::Impl(TcpImplError::InitializationError),
+            _ => TcpError::Impl(TcpImplError::Unknown),
+        }
+    }
+}
+
+impl<SPI, PIN> From<Error<SPI, PIN>> for DnsError {
+    fn from(e: Error<SPI, PIN>) -> Self {
+        match e {
+            Error::UnsupportedAddressType => DnsError::UnsupportedAddressType,
+            _ => DnsError::NoSuchHost,
+        }
+    }
+}
+
+
 #[derive(Debug)]
 pub enum JoinInfo<'a> {
-    Open,
+    Open {
+        ssid: &'a str,
+    },
     Wep {
         ssid: &'a str,
         password: &'a str,
     },
+    Wpa {
+        ssid: &'a str,
+        password: &'a str,
+    },
+    Wpa2 {
+        ssid: &'a str,
+        password: &'a str,
+    },
+    WpaMixed {
+        ssid: &'a str,
+        password: &'a str,
+    },
+}
+
+/// How the adapter obtains its address when joining a network.
+#[derive(Debug)]
+pub enum IpConfig {
+    Dhcp,
+    Static {
+        ip: Ipv4Addr,
+        netmask: Ipv4Addr,
+        gateway: Ipv4Addr,
+        primary_dns: Ipv4Addr,
+        secondary_dns: Option<Ipv4Addr>,
+    },
+}
+
+/// The network joined by `Adapter::join()`.
+#[derive(Debug)]
+pub struct JoinResult {
+    pub ssid: String<U32>,
+    /// The address assigned to the adapter, typically by DHCP.
+    pub ip: IpAddr,
+}
+
+/// The adapter's current network state, from `Adapter::network_status()`.
+#[derive(Debug)]
+pub struct NetworkStatus {
+    pub ssid: String<U32>,
+    pub ip: IpAddr,
+    pub netmask: IpAddr,
+    pub gateway: IpAddr,
+    pub primary_dns: IpAddr,
+    pub secondary_dns: IpAddr,
+    pub mac: [u8; 6],
+    /// Signal strength of the associated access point, in dBm.
+    pub rssi: i16,
+}
+
+/// An asynchronous notification from the adapter, from `Adapter::poll_event()`.
+///
+/// Sockets are identified by the `id()` of their `TcpSocket` or `UdpSocket`.
+#[derive(Debug)]
+pub enum Event {
+    /// The connection to the access point was lost.
+    Disconnected,
+    /// The peer closed its end of the connection on a socket.
+    PeerClosed {
+        socket: usize,
+    },
+    /// A client connected to a listening socket.
+    Accepted {
+        socket: usize,
+        remote: SocketAddr,
+    },
+    /// The module stopped responding and is being reset. All sockets are
+    /// lost, and any network must be joined again.
+    Reset,
+}
+
+/// Security of the access point hosted by the adapter.
+#[derive(Debug)]
+pub enum AccessPointSecurity<'a> {
+    Open,
+    Wpa2 {
+        password: &'a str,
+    },
+    WpaMixed {
+        password: &'a str,
+    },
 }
 
+/// Configuration for `Adapter::start_access_point()`.
 #[derive(Debug)]
-pub enum ConnectError {
-    SpiError(SpiError),
-    ConnectionFailed,
+pub struct AccessPointConfig<'a> {
+    pub ssid: &'a str,
+    pub security: AccessPointSecurity<'a>,
+    /// 2.4GHz channel, 1 through 13.
+    pub channel: u8,
+    pub max_clients: u8,
+    /// The adapter's own address on the access point's network.
+    pub ip: Ipv4Addr,
+    pub netmask: Ipv4Addr,
+    /// First address handed out by the DHCP server.
+    pub dhcp_start: Ipv4Addr,
+    /// Last address handed out by the DHCP server.
+    pub dhcp_end: Ipv4Addr,
 }
 
+/// A client associated with the adapter's access point.
 #[derive(Debug)]
-pub enum CloseError {
-    SpiError(SpiError),
-    Error,
+pub struct Station {
+    pub mac: [u8; 6],
+    pub ip: IpAddr,
+}
+
+/// Security of an access point, as reported by a scan.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SecurityType {
+    Open,
+    Wep,
+    Wpa,
+    Wpa2,
+    WpaMixed,
+    Wpa2Tkip,
+    Unknown,
 }
 
+/// An access point found by `Adapter::scan()`.
 #[derive(Debug)]
-pub enum WriteError {
-    Error,
-    SpiError(SpiError)
+pub struct ScanResult {
+    pub ssid: String<U32>,
+    pub bssid: [u8; 6],
+    pub rssi: i16,
+    pub channel: u8,
+    pub security: SecurityType,
 }
 
+/// The contents of a certificate slot on the module.
+#[derive(Debug, Copy, Clone)]
+pub enum CertificateKind {
+    CaCertificate = 0,
+    ClientCertificate = 1,
+    PrivateKey = 2,
+}
+
+/// Timing of the adapter's SPI transactions with the module, given to
+/// `Adapter::new()`.
+///
+/// The default follows the ISM43362's chip-select timing with some margin.
+/// The delays are only as precise as the adapter's clock, and round down on
+/// a coarser one.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct AdapterConfig {
+    /// How long chip-select is held low before each transfer, in microseconds.
+    pub cs_setup_us: u32,
+    /// How long chip-select is held high after each transfer, in microseconds.
+    pub cs_hold_us: u32,
+}
+
+impl AdapterConfig {
+    /// The 10 ms chip-select delays of earlier releases, for boards which
+    /// need more time than the module itself does.
+    pub fn conservative() -> Self {
+        Self {
+            cs_setup_us: 10_000,
+            cs_hold_us: 10_000,
+        }
+    }
+}
+
+impl Default for AdapterConfig {
+    fn default() -> Self {
+        Self {
+            cs_setup_us: 15,
+            cs_hold_us: 15,
+        }
+    }
+}
+
+/// TLS settings for `Adapter::connect_tls()`.
 #[derive(Debug)]
-pub enum ReadError {
-    Error,
-    SpiError(SpiError),
+pub struct TlsConfig {
+    /// The certificate slot holding the CA certificate, and optionally a
+    /// client certificate and private key, loaded by `Adapter::load_certificate()`.
+    pub slot: u8,
+    /// Whether the server's certificate must verify against the CA certificate.
+    pub verify_server: bool,
 }
 
 impl JoinInfo<'_> {
-    pub(crate) fn validate(&self) -> Result<&Self, JoinError> {
+    pub(crate) fn validate<SPI, PIN>(&self) -> Result<&Self, Error<SPI, PIN>> {
         match self {
-            JoinInfo::Open => {
-                Ok(self)
+            JoinInfo::Open { ssid } => {
+                if !is_valid_ssid(ssid) {
+                    Err(Error::InvalidSsid)
+                } else {
+                    Ok(self)
+                }
             }
             JoinInfo::Wep { ssid, password } => {
-                if ssid.len() > 32 {
-                    Err(JoinError::InvalidSsid)
-                } else if password.len() > 32 {
-                    Err(JoinError::InvalidPassword)
+                if !is_valid_ssid(ssid) {
+                    Err(Error::InvalidSsid)
+                } else if password.len() > 32 || password.contains('\r') {
+                    Err(Error::InvalidPassword)
+                } else {
+                    Ok(self)
+                }
+            }
+            JoinInfo::Wpa { ssid, password }
+            | JoinInfo::Wpa2 { ssid, password }
+            | JoinInfo::WpaMixed { ssid, password } => {
+                if !is_valid_ssid(ssid) {
+                    Err(Error::InvalidSsid)
+                } else if !is_valid_psk(password) {
+                    Err(Error::InvalidPassword)
                 } else {
                     Ok(self)
                 }
             }
-            _ => {
+        }
+    }
+}
+
+impl IpConfig {
+    pub(crate) fn validate<SPI, PIN>(&self) -> Result<&Self, Error<SPI, PIN>> {
+        match self {
+            IpConfig::Dhcp => {
                 Ok(self)
             }
+            IpConfig::Static { ip, netmask, gateway, primary_dns, secondary_dns } => {
+                let ip_bits = u32::from(*ip);
+                let mask_bits = u32::from(*netmask);
+                let gateway_bits = u32::from(*gateway);
+
+                // netmask must be a contiguous run of leading ones, leaving room for hosts
+                let host_bits = !mask_bits;
+                let valid_mask = mask_bits != 0 && (host_bits.wrapping_add(1) & host_bits) == 0 && host_bits > 1;
+                let valid_host = |addr: &Ipv4Addr, bits: u32| {
+                    !addr.is_unspecified()
+                        && !addr.is_multicast()
+                        && !addr.is_broadcast()
+                        && (bits & host_bits) != 0
+                        && (bits & host_bits) != host_bits
+                };
+                let valid_dns = |addr: &Ipv4Addr| {
+                    !addr.is_unspecified() && !addr.is_multicast() && !addr.is_broadcast()
+                };
+
+                if !valid_mask
+                    || !valid_host(ip, ip_bits)
+                    || !valid_host(gateway, gateway_bits)
+                    || ip == gateway
+                    || (ip_bits & mask_bits) != (gateway_bits & mask_bits)
+                    || !valid_dns(primary_dns)
+                    || !secondary_dns.as_ref().is_none_or(valid_dns)
+                {
+                    Err(Error::InvalidIpConfig)
+                } else {
+                    Ok(self)
+                }
+            }
         }
     }
 }
 
+impl AccessPointConfig<'_> {
+    pub(crate) fn validate<SPI, PIN>(&self) -> Result<&Self, Error<SPI, PIN>> {
+        if !is_valid_ssid(self.ssid) {
+            return Err(Error::InvalidSsid);
+        }
+
+        match self.security {
+            AccessPointSecurity::Open => {}
+            AccessPointSecurity::Wpa2 { password } | AccessPointSecurity::WpaMixed { password } => {
+                if !is_valid_psk(password) {
+                    return Err(Error::InvalidPassword);
+                }
+            }
+        }
+
+        if !(1..=13).contains(&self.channel) {
+            return Err(Error::InvalidChannel);
+        }
+
+        if self.max_clients == 0 {
+            return Err(Error::InvalidMaxClients);
+        }
+
+        let mask = u32::from(self.netmask);
+        let ip = u32::from(self.ip);
+        let start = u32::from(self.dhcp_start);
+        let end = u32::from(self.dhcp_end);
+        let same_subnet = |addr: u32| (addr & mask) == (ip & mask);
+
+        if mask == 0
+            || (ip & !mask) == 0
+            || !same_subnet(start)
+            || !same_subnet(end)
+            || start > end
+            || (start..=end).contains(&ip)
+        {
+            return Err(Error::InvalidIpConfig);
+        }
+
+        Ok(self)
+    }
+}
+
+/// An SSID is 1 to 32 bytes, and may not contain the module's command terminator.
+fn is_valid_ssid(ssid: &str) -> bool {
+    !ssid.is_empty() && ssid.len() <= 32 && !ssid.contains('\r')
+}
+
+/// A WPA pre-shared key is either an 8 to 63 character passphrase,
+/// or a raw 256-bit key given as 64 hex digits.
+fn is_valid_psk(password: &str) -> bool {
+    match password.len() {
+        8..=63 => !password.contains('\r'),
+        64 => password.bytes().all(|b| b.is_ascii_hexdigit()),
+        _ => false,
+    }
+}
 
 /// eS-WiFi Adapter, over SPI
-pub struct Adapter<'clock, Spi, ChipSelectPin, ReadyPin, WakeupPin, ResetPin, Clock>
+///
+/// `SOCKETS` is how many of the module's four sockets are used, and `BUFFER`
+/// the size of the buffer responses from the module are received into, which
+/// bounds how many scan results fit. Data read from a socket bypasses it.
+pub struct Adapter<'clock, Spi, ChipSelectPin, ReadyPin, WakeupPin, ResetPin, Clock, const SOCKETS: usize = 4, const BUFFER: usize = 1100>
     where
         Spi: Transfer<u8>,
         ChipSelectPin: OutputPin,
@@ -95,28 +471,37 @@ pub struct Adapter<'clock, Spi, ChipSelectPin, ReadyPin, WakeupPin, ResetPin, Cl
         ResetPin: OutputPin,
         Clock: embedded_time::Clock + 'clock
 {
-    pub(crate) arbiter: RefCell<Arbiter<'clock, Spi, ChipSelectPin, ReadyPin, WakeupPin, ResetPin, Clock>>,
-    pub(crate) sockets: RefCell<[Socket; 4]>,
+    pub(crate) arbiter: RefCell<Arbiter<'clock, Spi, ChipSelectPin, ReadyPin, WakeupPin, ResetPin, Clock, BUFFER>>,
+    pub(crate) sockets: RefCell<[Socket; SOCKETS]>,
+    pub(crate) events: RefCell<Queue<Event, U8>>,
     pub(crate) clock: &'clock Clock,
 }
 
 impl<'clock, Spi, ChipSelectPin, ReadyPin, WakeupPin, ResetPin, Clock> Adapter<'clock, Spi, ChipSelectPin, ReadyPin, WakeupPin, ResetPin, Clock>
     where
         Spi: Transfer<u8>,
+        Spi::Error: Debug,
         ChipSelectPin: OutputPin,
+        ChipSelectPin::Error: Debug,
         ReadyPin: InputPin,
         WakeupPin: OutputPin,
         ResetPin: OutputPin,
         Clock: embedded_time::Clock + 'clock
 {
-    /// Create a new eS-WiFi Adapter.
+    /// Create a new eS-WiFi Adapter, using all four of the module's sockets
+    /// and an 1100-byte response buffer. See `with_sizes()` to change those.
     ///
     /// * `spi`: The SPI transfer interface (u8).
     /// * `cs`: The chip-select pin for the adapter.
     /// * `ready`: The input pin to know when the adapter is ready for a data phase.
     /// * `wakeup`: The adapter's wake-up pin.
     /// * `reset`: The adapter's reset pin.
-    /// * `clock`: A clock capable of 10ms precision
+    /// * `clock`: A clock capable of 10ms precision, or of the chip-select
+    ///   delays in `config` if finer.
+    /// * `config`: The timing of SPI transactions, usually `AdapterConfig::default()`.
+    ///
+    /// Only the chip-select pin is driven here; the adapter is not touched
+    /// until `init()` or the first request.
     pub fn new(
         spi: Spi,
         cs: ChipSelectPin,
@@ -124,42 +509,263 @@ impl<'clock, Spi, ChipSelectPin, ReadyPin, WakeupPin, ResetPin, Clock> Adapter<'
         wakeup: WakeupPin,
         reset: ResetPin,
         clock: &'clock Clock,
-    ) -> Result<Self, ()> {
-        let mut arbiter = Arbiter::new(
+        config: AdapterConfig,
+    ) -> Result<Self, Error<Spi::Error, ChipSelectPin::Error>> {
+        Self::with_sizes(spi, cs, ready, wakeup, reset, clock, config)
+    }
+}
+
+impl<'clock, Spi, ChipSelectPin, ReadyPin, WakeupPin, ResetPin, Clock, const SOCKETS: usize, const BUFFER: usize> Adapter<'clock, Spi, ChipSelectPin, ReadyPin, WakeupPin, ResetPin, Clock, SOCKETS, BUFFER>
+    where
+        Spi: Transfer<u8>,
+        Spi::Error: Debug,
+        ChipSelectPin: OutputPin,
+        ChipSelectPin::Error: Debug,
+        ReadyPin: InputPin,
+        WakeupPin: OutputPin,
+        ResetPin: OutputPin,
+        Clock: embedded_time::Clock + 'clock
+{
+    const VALID_SIZES: () = {
+        assert!(SOCKETS >= 1 && SOCKETS <= 4, "the module has four sockets");
+        // the longest response other than socket data or a scan
+        assert!(BUFFER >= 256, "BUFFER must be at least 256 bytes");
+    };
+
+    /// Create an adapter with `SOCKETS` sockets and a `BUFFER`-byte response
+    /// buffer, taken from its type:
+    ///
+    /// ```ignore
+    /// let wifi: Adapter<_, _, _, _, _, _, 2, 512> = Adapter::with_sizes(spi, cs, ready, wakeup, reset, &CLOCK, AdapterConfig::default())?;
+    /// ```
+    ///
+    /// Otherwise the same as `new()`.
+    pub fn with_sizes(
+        spi: Spi,
+        cs: ChipSelectPin,
+        ready: ReadyPin,
+        wakeup: WakeupPin,
+        reset: ResetPin,
+        clock: &'clock Clock,
+        config: AdapterConfig,
+    ) -> Result<Self, Error<Spi::Error, ChipSelectPin::Error>> {
+        let () = Self::VALID_SIZES;
+
+        let arbiter = Arbiter::new(
             spi,
             cs,
             ready,
             wakeup,
             reset,
             clock,
-        );
+            config,
+        )?;
 
         Ok(Self {
             arbiter: RefCell::new(arbiter),
             sockets: RefCell::new(Socket::create()),
+            events: RefCell::new(Queue::new()),
             clock,
         })
     }
 
+    /// Reset and initialize the adapter.
+    ///
+    /// Without this, the adapter is initialized by its first request. If
+    /// initialization fails, requests fail with `Error::NotReady` until
+    /// `init()` succeeds. Any open sockets are invalidated.
+    pub fn init(&mut self) -> Result<(), Error<Spi::Error, ChipSelectPin::Error>> {
+        let mut arbiter = self.arbiter.borrow_mut();
+        for socket in self.sockets.borrow_mut().iter_mut().filter(|s| s.is_open() || s.is_listening()) {
+            socket.state = State::HalfClosed;
+        }
+        arbiter.initialize()
+    }
+
+    /// Take the oldest unsolicited event reported by the adapter, checking
+    /// the module for new ones first.
+    ///
+    /// Events are also collected as a side-effect of requests on sockets;
+    /// socket state is updated from them whether or not they are polled.
+    /// When the queue is full, the oldest events are dropped.
+    pub fn poll_event(&self) -> Option<Event> {
+        self.arbiter.borrow_mut().poll();
+        self.dispatch_events();
+        self.events.borrow_mut().dequeue()
+    }
+
+    /// Invalidate sockets lost to a reset of the module, then apply events
+    /// collected by the arbiter to the socket table, and queue them for
+    /// `poll_event()`.
+    pub(crate) fn dispatch_events(&self) {
+        let mut arbiter = self.arbiter.borrow_mut();
+        let mut sockets = self.sockets.borrow_mut();
+        let mut events = self.events.borrow_mut();
+
+        let generation = arbiter.generation();
+        for socket in sockets.iter_mut().filter(|s| s.is_connected() || s.is_listening()) {
+            if socket.generation != generation {
+                socket.state = State::HalfClosed;
+            }
+        }
+
+        while let Some(event) = arbiter.take_event() {
+            match event {
+                Event::Disconnected => {
+                    for socket in sockets.iter_mut().filter(|s| s.is_open()) {
+                        socket.state = State::HalfClosed;
+                    }
+                }
+                // the sockets it concerns were invalidated by generation above,
+                // while those opened since are unaffected
+                Event::Reset => {}
+                Event::PeerClosed { socket } => {
+                    if sockets[socket].is_open() {
+                        sockets[socket].state = State::HalfClosed;
+                    }
+                }
+                Event::Accepted { socket, remote } => {
+                    if sockets[socket].is_listening() {
+                        sockets[socket].state = State::Connected;
+                        sockets[socket].remote = Some(remote);
+                    }
+                }
+            }
+
+            if let Err(event) = events.enqueue(event) {
+                events.dequeue();
+                events.enqueue(event).ok();
+            }
+        }
+    }
+
     /// Join a WiFi access point.
-    pub fn join(&mut self, join_info: &JoinInfo) -> Result<(), JoinError> {
+    ///
+    /// Both `join_info` and `ip_config` are validated before anything is
+    /// sent to the adapter.
+    pub fn join(&mut self, join_info: &JoinInfo, ip_config: &IpConfig) -> Result<JoinResult, Error<Spi::Error, ChipSelectPin::Error>> {
         join_info.validate()?;
+        ip_config.validate()?;
         let mut arbiter = self.arbiter.borrow_mut();
-        arbiter.join(join_info)
+        arbiter.join(join_info, ip_config)
     }
 
     /// Join an open WiFi access point.
-    pub fn join_open(&mut self) -> Result<(), JoinError> {
-        self.join(&JoinInfo::Open)
+    pub fn join_open(&mut self, ssid: &str) -> Result<JoinResult, Error<Spi::Error, ChipSelectPin::Error>> {
+        self.join(
+            &JoinInfo::Open {
+                ssid,
+            },
+            &IpConfig::Dhcp,
+        )
     }
 
     /// Join a WEP-secured WiFI access point.
-    pub fn join_wep(&mut self, ssid: &str, password: &str) -> Result<(), JoinError> {
+    pub fn join_wep(&mut self, ssid: &str, password: &str) -> Result<JoinResult, Error<Spi::Error, ChipSelectPin::Error>> {
         self.join(
             &JoinInfo::Wep {
                 ssid,
                 password,
+            },
+            &IpConfig::Dhcp,
+        )
+    }
+
+    /// Leave the currently-joined WiFi access point.
+    ///
+    /// All open sockets are closed first, and are left in the closed state
+    /// even if the adapter fails to close them cleanly.
+    pub fn leave(&mut self) -> Result<(), Error<Spi::Error, ChipSelectPin::Error>> {
+        let mut arbiter = self.arbiter.borrow_mut();
+        for (index, socket) in self.sockets.borrow_mut().iter_mut().enumerate() {
+            if socket.is_open() {
+                if let Err(e) = arbiter.close(index) {
+                    log::info!("error closing socket {} while leaving: {:?}", index, e);
+                }
+            } else if socket.is_listening() {
+                if let Err(e) = arbiter.stop_listening(index) {
+                    log::info!("error stopping server {} while leaving: {:?}", index, e);
+                }
             }
+            socket.state = State::Closed;
+        }
+        arbiter.leave()
+    }
+
+    /// Load a PEM-encoded certificate or private key into a certificate slot on the module.
+    pub fn load_certificate(&mut self, kind: CertificateKind, slot: u8, data: &[u8]) -> Result<(), Error<Spi::Error, ChipSelectPin::Error>> {
+        if data.is_empty() {
+            return Err(Error::EmptyCertificate);
+        }
+        let mut arbiter = self.arbiter.borrow_mut();
+        arbiter.load_certificate(kind, slot, data)
+    }
+
+    /// Host a WiFi access point, for instance to provision the device.
+    ///
+    /// Sockets keep working while the adapter is an access point.
+    pub fn start_access_point(&mut self, config: &AccessPointConfig) -> Result<(), Error<Spi::Error, ChipSelectPin::Error>> {
+        config.validate()?;
+        let mut arbiter = self.arbiter.borrow_mut();
+        arbiter.start_access_point(config)
+    }
+
+    /// Stop hosting the access point started by `start_access_point()`.
+    pub fn stop_access_point(&mut self) -> Result<(), Error<Spi::Error, ChipSelectPin::Error>> {
+        let mut arbiter = self.arbiter.borrow_mut();
+        arbiter.stop_access_point()
+    }
+
+    /// List the clients associated with the adapter's access point.
+    ///
+    /// Previous contents of `stations` are cleared. Stations beyond the
+    /// capacity of `stations` are dropped.
+    pub fn access_point_stations<N: ArrayLength<Station>>(&mut self, stations: &mut Vec<Station, N>) -> Result<(), Error<Spi::Error, ChipSelectPin::Error>> {
+        let mut arbiter = self.arbiter.borrow_mut();
+        arbiter.access_point_stations(stations)
+    }
+
+    /// Scan for nearby access points.
+    ///
+    /// Previous contents of `results` are cleared. Results beyond the
+    /// capacity of `results` are dropped.
+    ///
+    /// The module lists every access point in range in one response, taking
+    /// up to 100 bytes for each. If that overflows the adapter's `BUFFER`, the
+    /// results which fit are kept and `Error::ResponseTruncated` is returned;
+    /// a `BUFFER` of 2048 bytes has room for 20 access points.
+    pub fn scan<N: ArrayLength<ScanResult>>(&mut self, results: &mut Vec<ScanResult, N>) -> Result<(), Error<Spi::Error, ChipSelectPin::Error>> {
+        let mut arbiter = self.arbiter.borrow_mut();
+        arbiter.scan(results)
+    }
+
+    /// Query the current network state of the adapter.
+    pub fn network_status(&mut self) -> Result<NetworkStatus, Error<Spi::Error, ChipSelectPin::Error>> {
+        let mut arbiter = self.arbiter.borrow_mut();
+        arbiter.network_status()
+    }
+
+    /// Estimate the channel of the joined access point, or `None` if not joined
+    /// or the access point wasn't heard.
+    ///
+    /// The module doesn't report the channel it joined on, so this scans for
+    /// access points with the joined SSID and takes the one whose signal is
+    /// nearest the joined RSSI, which need not be the one joined. The scan
+    /// takes a few seconds, so call this sparingly rather than with every
+    /// `network_status()`.
+    pub fn estimate_channel(&mut self) -> Result<Option<u8>, Error<Spi::Error, ChipSelectPin::Error>> {
+        let mut arbiter = self.arbiter.borrow_mut();
+        arbiter.estimate_channel()
+    }
+
+    /// Join a WPA2-PSK-secured WiFi access point.
+    pub fn join_wpa2(&mut self, ssid: &str, password: &str) -> Result<JoinResult, Error<Spi::Error, ChipSelectPin::Error>> {
+        self.join(
+            &JoinInfo::Wpa2 {
+                ssid,
+                password,
+            },
+            &IpConfig::Dhcp,
         )
     }
 
@@ -1,51 +1,87 @@
 use embedded_hal::blocking::spi::Transfer;
 use embedded_hal::digital::v2::{OutputPin, InputPin};
-use embedded_time::duration::Milliseconds;
+use embedded_time::duration::{Microseconds, Milliseconds};
 use drogue_embedded_timer::Delay;
-use heapless::{consts::*, String, spsc::{Consumer, Producer}, ArrayLength};
+use heapless::{consts::*, String, Vec, spsc::Queue, ArrayLength};
 
-use core::fmt::Write;
+use core::fmt::{Debug, Write};
 
 use crate::chip_select::ChipSelect;
 use crate::ready::Ready;
-use nom::InputIter;
-use crate::adapter::{AdapterError, JoinError, JoinInfo, ConnectError, WriteError, ReadError, CloseError};
+use crate::adapter::{AdapterConfig, ControlPin, Error, JoinInfo, JoinResult, IpConfig, ScanResult, NetworkStatus, TlsConfig, CertificateKind, AccessPointConfig, AccessPointSecurity, Station, Event};
 use crate::parser;
-use crate::parser::{JoinResponse, ConnectResponse, WriteResponse, ReadResponse, CloseResponse};
-use nom::error::ErrorKind;
-use drogue_network::addr::HostSocketAddr;
+use crate::parser::{JoinResponse, ConnectResponse, WriteResponse, ReadResponse, CloseResponse, ListResponse, CommandResponse, MessageResponse, Message, ResolveResponse, SocketStatus};
+use drogue_network::addr::{HostSocketAddr, IpAddr, SocketAddr};
 
 macro_rules! command {
     ($size:tt, $($arg:tt)*) => ({
-        //let mut c = String::new();
-        //c
+        // callers size the command for the longest valid arguments
         let mut c = String::<$size>::new();
-        write!(c, $($arg)*);
-        c.push_str("\r");
+        write!(c, $($arg)*).ok();
+        c.push('\r').ok();
         c
     })
 }
 
+/// Largest payload the module accepts in a single `S0` write.
+pub(crate) const MAX_WRITE_LEN: usize = 1046;
+
+/// Largest payload the module returns from a single `R0` read.
+const MAX_READ_LEN: usize = 1460;
+
+/// Bounds on how long, in milliseconds, the module waits for data to read or
+/// room to write before answering an `R0` or `S0` (its `R2` and `S2`). The
+/// host waits on the ready pin meanwhile, so the longest stays well inside
+/// `READY_TIMEOUT`.
+const MIN_SOCKET_WAIT: u32 = 1;
+const MAX_SOCKET_WAIT: u32 = 20_000;
+
+/// Longest the module may stay busy, which must cover a join or scan.
+const READY_TIMEOUT: Milliseconds<u32> = Milliseconds(30_000);
+
+/// Most unsolicited messages read from the module per request.
+const MAX_BACKLOG_MESSAGES: usize = 4;
+
+/// The adapter's `Error`, for the HAL types of an `Arbiter`.
+type ArbiterError<Spi, Pin> = Error<<Spi as Transfer<u8>>::Error, <Pin as OutputPin>::Error>;
+
 enum State {
     Uninitialized,
     Ready,
+    Failed,
 }
 
+/// A setting of the selected socket, as set by the command it names.
+#[derive(Copy, Clone)]
+enum Setting {
+    ReadLength,
+    ReadWait,
+    ReadMode,
+    WriteLength,
+    WriteWait,
+}
 
-#[derive(Debug)]
-pub enum SpiError {
-    ReadError,
-    WriteError,
+impl Setting {
+    fn command(self) -> &'static str {
+        match self {
+            Setting::ReadLength => "R1",
+            Setting::ReadWait => "R2",
+            Setting::ReadMode => "R3",
+            Setting::WriteLength => "S1",
+            Setting::WriteWait => "S2",
+        }
+    }
 }
 
 #[derive(Debug)]
 pub enum IpProtocol {
     Tcp,
     Udp,
+    Tls,
 }
 
 
-pub struct Arbiter<'clock, Spi, ChipSelectPin, ReadyPin, WakeupPin, ResetPin, Clock>
+pub struct Arbiter<'clock, Spi, ChipSelectPin, ReadyPin, WakeupPin, ResetPin, Clock, const BUFFER: usize>
     where
         Spi: Transfer<u8>,
         ChipSelectPin: OutputPin,
@@ -62,12 +98,31 @@ pub struct Arbiter<'clock, Spi, ChipSelectPin, ReadyPin, WakeupPin, ResetPin, Cl
     clock: &'clock Clock,
     delay: Delay<'clock, Clock>,
     state: State,
+    selected: Option<usize>,
+    /// The selected socket's settings as last set, indexed by `Setting`, so
+    /// unchanged ones aren't sent again. `None` where unknown.
+    settings: [Option<u32>; 5],
+    /// Which of the module's sockets are listening for clients.
+    listening: [bool; 4],
+    /// Which of the module's sockets hold a TCP connection, which the peer
+    /// may close.
+    connected: [bool; 4],
+    /// Counts the resets that lost the module's sockets, so that sockets
+    /// opened before one can be told apart from those opened since.
+    generation: u32,
+    events: Queue<Event, U8>,
+    /// Every response is received here, so it bounds the longest one.
+    buffer: [u8; BUFFER],
+    /// Whether the last response was longer than where it was received.
+    truncated: bool,
 }
 
-impl<'clock, Spi, ChipSelectPin, ReadyPin, WakeupPin, ResetPin, Clock> Arbiter<'clock, Spi, ChipSelectPin, ReadyPin, WakeupPin, ResetPin, Clock>
+impl<'clock, Spi, ChipSelectPin, ReadyPin, WakeupPin, ResetPin, Clock, const BUFFER: usize> Arbiter<'clock, Spi, ChipSelectPin, ReadyPin, WakeupPin, ResetPin, Clock, BUFFER>
     where
         Spi: Transfer<u8>,
+        Spi::Error: Debug,
         ChipSelectPin: OutputPin,
+        ChipSelectPin::Error: Debug,
         ReadyPin: InputPin,
         WakeupPin: OutputPin,
         ResetPin: OutputPin,
@@ -79,430 +134,993 @@ impl<'clock, Spi, ChipSelectPin, ReadyPin, WakeupPin, ResetPin, Clock> Arbiter<'
                wakeup: WakeupPin,
                reset: ResetPin,
                clock: &'clock Clock,
-    ) -> Self {
-        Self {
+               config: AdapterConfig,
+    ) -> Result<Self, ArbiterError<Spi, ChipSelectPin>> {
+        let setup = Microseconds(config.cs_setup_us);
+        let hold = Microseconds(config.cs_hold_us);
+        Ok(Self {
             spi,
-            cs: ChipSelect::new(cs, Delay::new(clock)),
+            cs: ChipSelect::new(cs, Delay::new(clock), setup, hold).map_err(Error::Pin)?,
             ready: Ready::new(ready),
             wakeup,
             reset,
             clock,
             delay: Delay::new(clock),
             state: State::Uninitialized,
-        }
+            selected: None,
+            settings: [None; 5],
+            listening: [false; 4],
+            connected: [false; 4],
+            generation: 0,
+            events: Queue::new(),
+            buffer: [0; BUFFER],
+            truncated: false,
+        })
+    }
+
+    /// Reset the module and wait for its prompt. On failure, requests are
+    /// refused with `Error::NotReady` until this is called again.
+    pub(crate) fn initialize(&mut self) -> Result<(), ArbiterError<Spi, ChipSelectPin>> {
+        let result = self.initialize_module();
+        self.forget_sockets();
+        self.state = if result.is_ok() {
+            log::info!("eS-WiFi adapter is ready");
+            State::Ready
+        } else {
+            State::Failed
+        };
+        result
     }
 
-    fn initialize(&mut self) -> Result<(), ()> {
-        self.wakeup();
-        self.reset();
+    fn initialize_module(&mut self) -> Result<(), ArbiterError<Spi, ChipSelectPin>> {
+        // no reset or data-ready is reported while initializing
+        self.state = State::Uninitialized;
+
+        self.wakeup()?;
+        self.reset()?;
 
         //log::info!("await ready");
-        self.await_data_ready();
+        self.await_data_ready().map_err(|e| match e {
+            Error::Unresponsive => Error::NoReadySignal,
+            e => e,
+        })?;
         //log::info!("ready");
 
-        let _cs = self.cs.select();
+        let timer = self.clock.new_timer(READY_TIMEOUT).start().map_err(|_| Error::Timeout)?;
+
+        let cs = self.cs.select().map_err(Error::Pin)?;
 
-        let mut response = [0 as u8; 16];
+        let mut response = [0u8; 16];
         let mut pos = 0;
 
-        loop {
+        while self.ready.is_ready().map_err(|_| Error::ControlPin(ControlPin::Ready))? {
             //log::info!("loop {}", pos);
-            if !self.ready.is_ready() {
-                break;
-            }
-            if pos >= response.len() {
-                return Err(());
+            // a ready pin stuck high never gets to the prompt
+            if let Ok(true) = timer.is_expired() {
+                return Err(Error::BadBanner);
             }
             let mut chunk = [0x0A, 0x0A];
-            self.spi.transfer(&mut chunk);
+            self.spi.transfer(&mut chunk).map_err(Error::Spi)?;
             //log::info!("transfer {:?}", chunk);
             // reverse order going from 16 -> 2*8 bits
-            if chunk[1] != 0x15 {
-                response[pos] = chunk[1];
-                pos += 1;
-            }
-            if chunk[0] != 0x15 {
-                response[pos] = chunk[0];
+            for byte in [chunk[1], chunk[0]].iter().filter(|b| **b != 0x15) {
+                if pos >= response.len() {
+                    return Err(Error::BadBanner);
+                }
+                response[pos] = *byte;
                 pos += 1;
             }
         }
 
-        let needle = &[b'\r', b'\n', b'>', b' '];
+        let needle = b"\r\n> ";
 
-        drop(_cs);
+        drop(cs);
 
         if !response[0..pos].starts_with(needle) {
             log::info!("failed to initialize {:?}", &response[0..pos]);
-            Err(())
-        } else {
-            // disable verbosity
-            self.send_string(&command!(U8, "MT=1"), &mut response);
-            self.state = State::Ready;
-            log::info!("eS-WiFi adapter is ready");
-            Ok(())
+            return Err(Error::BadBanner);
         }
+
+        // disable verbosity
+        self.send_command(&command!(U8, "MT=1"))
     }
 
-    fn process_backlog(&mut self) {
-        if matches!(self.state, State::Uninitialized) {
-            self.initialize();
+    /// Lazily initialize the module, unless a previous attempt failed, in
+    /// which case only an explicit `initialize()` will try again.
+    fn ensure_ready(&mut self) -> Result<(), ArbiterError<Spi, ChipSelectPin>> {
+        match self.state {
+            State::Ready => Ok(()),
+            State::Failed => Err(Error::NotReady),
+            State::Uninitialized => {
+                self.initialize().map_err(|e| {
+                    log::info!("failed to initialize: {:?}", e);
+                    e
+                })
+            }
         }
+    }
 
-        /*
-        let mut response = [0u8; 1024];
+    /// Initialize the module if needed and drain its unsolicited messages
+    /// into the event queue, then select `socket_num`.
+    fn process_backlog(&mut self, socket_num: usize) -> Result<(), ArbiterError<Spi, ChipSelectPin>> {
+        self.ensure_ready()?;
+        self.drain_messages();
+        self.select_socket(socket_num)
+    }
 
-        let response = self.send_string(
-            &command!(U4, "MR"),
-                &mut response
-        );
+    /// Drain the module's unsolicited messages into the event queue.
+    ///
+    /// Messages don't say which socket they concern, so the module is asked
+    /// with `P?` which socket lost its connection, or took a client when
+    /// several are listening.
+    fn drain_messages(&mut self) {
+        // bounded, so a chatty module can't starve the request being made
+        for _ in 0..MAX_BACKLOG_MESSAGES {
+            let response = match self.send_string(&command!(U4, "MR")) {
+                Ok(response) => response,
+                Err(e) => {
+                    log::info!("unable to read backlog: {:?}", e);
+                    return;
+                }
+            };
+
+            let message = match parser::message_response(response) {
+                Ok((_, MessageResponse::Message(message))) => message,
+                Ok((_, MessageResponse::Empty)) => return,
+                Err(_) => {
+                    log::info!("unparseable backlog {:?}", response);
+                    return;
+                }
+            };
+
+            match message {
+                Message::Disconnected => self.queue_event(Event::Disconnected),
+                Message::PeerClosed => self.confirm_closes(),
+                Message::Accepted(remote) => {
+                    let socket = self.listener(remote);
+                    // the listening socket now holds the client
+                    self.listening[socket] = false;
+                    self.connected[socket] = true;
+                    self.queue_event(Event::Accepted { socket, remote });
+                }
+                Message::Other(body) => {
+                    log::info!("ignoring message {:?}", core::str::from_utf8(body));
+                }
+            }
+        }
+    }
+
+    fn queue_event(&mut self, event: Event) {
+        log::info!("event {:?}", event);
+
+        if self.events.enqueue(event).is_err() {
+            log::info!("event queue full, dropping event");
+        }
+    }
+
+    /// Ask each connected socket whether it still is, after the module
+    /// reported a close, and report those which are not.
+    fn confirm_closes(&mut self) {
+        for socket in 0..self.connected.len() {
+            if !self.connected[socket] {
+                continue;
+            }
+            match self.socket_status(socket) {
+                Ok(status) if !status.connected => {
+                    self.connected[socket] = false;
+                    self.queue_event(Event::PeerClosed { socket });
+                }
+                Ok(_) => {}
+                Err(e) => log::info!("unable to query socket {}: {:?}", socket, e),
+            }
+        }
+    }
+
+    /// The listening socket a client from `remote` was accepted on: the only
+    /// one, or else the one whose status shows the client.
+    fn listener(&mut self, remote: SocketAddr) -> usize {
+        let first = match self.listening.iter().position(|listening| *listening) {
+            Some(first) => first,
+            None => {
+                log::info!("client accepted with no socket listening");
+                return 0;
+            }
+        };
+        if self.listening.iter().filter(|listening| **listening).count() == 1 {
+            return first;
+        }
+
+        for socket in first..self.listening.len() {
+            if !self.listening[socket] {
+                continue;
+            }
+            match self.socket_status(socket) {
+                Ok(status) if status.connected && status.remote == remote => return socket,
+                Ok(_) => {}
+                Err(e) => log::info!("unable to query socket {}: {:?}", socket, e),
+            }
+        }
+        first
+    }
 
-        if response.is_ok() {
-            let response = response.unwrap();
-            log::info!( "backlog {}", core::str::from_utf8(&response).unwrap());
+    /// Whether the module confirms that `socket_num` lost its connection,
+    /// after a read or write on it failed.
+    pub(crate) fn confirm_closed(&mut self, socket_num: usize) -> bool {
+        match self.socket_status(socket_num) {
+            Ok(status) => {
+                self.connected[socket_num] &= status.connected;
+                !status.connected
+            }
+            Err(e) => {
+                log::info!("unable to query socket {}: {:?}", socket_num, e);
+                false
+            }
         }
-         */
     }
 
-    fn wakeup(&mut self) {
-        self.wakeup.set_low();
+    /// Query the connection of `socket_num` with `P?`.
+    fn socket_status(&mut self, socket_num: usize) -> Result<SocketStatus, ArbiterError<Spi, ChipSelectPin>> {
+        self.select_socket(socket_num)?;
+        let response = self.send_string(&command!(U4, "P?"))?;
+
+        match parser::socket_status(response) {
+            Ok((_, status)) => Ok(status),
+            Err(_) => match parser::command_response(response) {
+                Ok((_, CommandResponse::Error)) => Err(Error::Module(parser::module_error(response))),
+                _ => Err(Error::UnexpectedResponse),
+            },
+        }
     }
 
-    fn reset(&mut self) {
-        self.reset.set_low();
+    /// Changes whenever a reset loses the module's sockets. A socket opened
+    /// on the module in an earlier generation no longer exists.
+    pub(crate) fn generation(&self) -> u32 {
+        self.generation
+    }
+
+    /// Take the oldest event found while processing the backlog.
+    pub(crate) fn take_event(&mut self) -> Option<Event> {
+        self.events.dequeue()
+    }
+
+    /// Select the socket that subsequent `P`, `R` and `S` commands apply to,
+    /// unless it already is.
+    fn select_socket(&mut self, socket_num: usize) -> Result<(), ArbiterError<Spi, ChipSelectPin>> {
+        if self.selected == Some(socket_num) {
+            return Ok(());
+        }
+
+        // the settings may not carry over to another socket
+        self.selected = None;
+        self.settings = [None; 5];
+        self.send_command(&command!(U8, "P0={}", socket_num))?;
+        self.selected = Some(socket_num);
+        Ok(())
+    }
+
+    /// Set one of the selected socket's settings, unless it already holds `value`.
+    fn set(&mut self, setting: Setting, value: u32) -> Result<(), ArbiterError<Spi, ChipSelectPin>> {
+        let cached = &mut self.settings[setting as usize];
+        if *cached == Some(value) {
+            return Ok(());
+        }
+
+        // whether a failed command took effect is unknown
+        *cached = None;
+        self.send_command(&command!(U16, "{}={}", setting.command(), value))?;
+        self.settings[setting as usize] = Some(value);
+        Ok(())
+    }
+
+    fn wakeup(&mut self) -> Result<(), ArbiterError<Spi, ChipSelectPin>> {
+        self.wakeup.set_low().map_err(|_| Error::ControlPin(ControlPin::Wakeup))
+    }
+
+    fn reset(&mut self) -> Result<(), ArbiterError<Spi, ChipSelectPin>> {
+        self.reset.set_low().map_err(|_| Error::ControlPin(ControlPin::Reset))?;
         self.delay.delay(Milliseconds(10u32));
-        self.reset.set_high();
+        self.reset.set_high().map_err(|_| Error::ControlPin(ControlPin::Reset))?;
         self.delay.delay(Milliseconds(10u32));
+        Ok(())
     }
 
-    fn await_data_ready(&mut self) {
-        while !self.ready.is_ready() {
-            continue;
+    /// Wait for the module to raise its ready pin.
+    ///
+    /// A module which stays busy past `READY_TIMEOUT` is presumed hung, and
+    /// is reset and re-initialized on the next request.
+    fn await_data_ready(&mut self) -> Result<(), ArbiterError<Spi, ChipSelectPin>> {
+        let timer = self.clock.new_timer(READY_TIMEOUT).start().map_err(|_| Error::Timeout)?;
+
+        while !self.ready.is_ready().map_err(|_| Error::ControlPin(ControlPin::Ready))? {
+            if let Ok(true) = timer.is_expired() {
+                log::info!("eS-WiFi adapter not ready after {:?}", READY_TIMEOUT);
+                self.recover();
+                return Err(Error::Unresponsive);
+            }
+        }
+        Ok(())
+    }
+
+    /// Arrange for the module to be reset and re-initialized on the next
+    /// request. Sockets on the module do not survive that, so they are
+    /// invalidated at once by a new `generation()`, and the application is
+    /// told through an `Event::Reset`.
+    fn recover(&mut self) {
+        if matches!(self.state, State::Ready) && self.events.enqueue(Event::Reset).is_err() {
+            // the reset must be delivered, at the expense of older events
+            self.events.dequeue();
+            self.events.enqueue(Event::Reset).ok();
+        }
+        self.state = State::Uninitialized;
+        self.forget_sockets();
+    }
+
+    /// Forget which socket the module has selected, how it is set and which
+    /// sockets are listening, as after a reset, and start a new generation.
+    fn forget_sockets(&mut self) {
+        self.generation = self.generation.wrapping_add(1);
+        self.selected = None;
+        self.settings = [None; 5];
+        self.listening = [false; 4];
+        self.connected = [false; 4];
+    }
+
+    /// Send a command which only reports success or failure.
+    fn send_command<N: ArrayLength<u8>>(&mut self, command: &String<N>) -> Result<(), ArbiterError<Spi, ChipSelectPin>> {
+        let response = self.send_string(command)?;
+
+        match parser::command_response(response) {
+            Ok((_, CommandResponse::Ok)) => Ok(()),
+            Ok((_, CommandResponse::Error)) => Err(Error::Module(parser::module_error(response))),
+            Err(_) => Err(Error::UnexpectedResponse),
         }
     }
 
-    fn send_string<'a, N: ArrayLength<u8>>(&mut self, command: &String<N>, response: &'a mut [u8]) -> Result<&'a [u8], SpiError> {
-        self.send(command.as_bytes(), response)
+    fn send_string<N: ArrayLength<u8>>(&mut self, command: &String<N>) -> Result<&[u8], ArbiterError<Spi, ChipSelectPin>> {
+        self.send(command.as_bytes())
     }
 
-    fn send<'a>(&mut self, command: &[u8], response: &'a mut [u8]) -> Result<&'a [u8], SpiError> {
+    fn send(&mut self, command: &[u8]) -> Result<&[u8], ArbiterError<Spi, ChipSelectPin>> {
         //log::info!("send {:?}", core::str::from_utf8(command).unwrap());
+        self.send_with_payload(command, &[])
+    }
 
-        self.await_data_ready();
+    /// Send a command immediately followed by a binary payload, in a single
+    /// transaction, padding an odd total length with a trailing `\n`.
+    fn send_with_payload(&mut self, command: &[u8], payload: &[u8]) -> Result<&[u8], ArbiterError<Spi, ChipSelectPin>> {
+        self.await_data_ready()?;
         {
-            let _cs = self.cs.select();
+            let _cs = self.cs.select().map_err(Error::Pin)?;
 
-            for chunk in command.chunks(2) {
-                let mut xfer: [u8; 2] = [0; 2];
-                xfer[1] = chunk[0];
-                if chunk.len() == 2 {
-                    xfer[0] = chunk[1]
-                } else {
-                    xfer[0] = 0x0A
-                }
+            let mut bytes = command.iter().chain(payload.iter());
 
-                let result = self.spi.transfer(&mut xfer);
-                if !result.is_ok() {
-                    return Err(SpiError::WriteError);
+            while let Some(first) = bytes.next() {
+                let mut xfer: [u8; 2] = [0x0A, *first];
+                if let Some(second) = bytes.next() {
+                    xfer[0] = *second;
                 }
+
+                self.spi.transfer(&mut xfer).map_err(Error::Spi)?;
             }
         }
-        self.receive(response)
+        self.receive()
     }
 
-    fn receive<'a>(&mut self, response: &'a mut [u8]) -> Result<&'a [u8], SpiError> {
-        self.await_data_ready();
+    fn receive(&mut self) -> Result<&[u8], ArbiterError<Spi, ChipSelectPin>> {
+        let len = self.receive_into(&mut Destination::Scratch)?;
+        Ok(&self.buffer[0..len])
+    }
+
+    /// Clock out a response into `destination`, returning its length.
+    fn receive_into(&mut self, destination: &mut Destination<'_>) -> Result<usize, ArbiterError<Spi, ChipSelectPin>> {
+        self.await_data_ready()?;
         let mut pos = 0;
 
-        let _cs = self.cs.select();
+        // a ready pin stuck high would otherwise clock out garbage forever
+        let timer = self.clock.new_timer(READY_TIMEOUT).start().map_err(|_| Error::Timeout)?;
 
-        while self.ready.is_ready() {
-            let mut xfer: [u8; 2] = [0x0A, 0x0A];
-            let result = self.spi.transfer(&mut xfer);
-            if !result.is_ok() {
-                return Err(SpiError::ReadError);
+        let cs = self.cs.select().map_err(Error::Pin)?;
+
+        let mut padded = false;
+        self.truncated = false;
+        while self.ready.is_ready().map_err(|_| Error::ControlPin(ControlPin::Ready))? {
+            if let Ok(true) = timer.is_expired() {
+                drop(cs);
+                self.recover();
+                return Err(Error::Unresponsive);
             }
+            let mut xfer: [u8; 2] = [0x0A, 0x0A];
+            self.spi.transfer(&mut xfer).map_err(Error::Spi)?;
             //log::info!( "read {} {}", xfer[1] as char, xfer[0] as char);
-            response[pos] = xfer[1];
-            pos += 1;
-            if xfer[0] != 0x15 {
-                response[pos] = xfer[0];
+            // keep clocking out an over-long response, but drop what doesn't fit
+            if let Some(slot) = destination.slot(&mut self.buffer, pos) {
+                *slot = xfer[1];
+                pos += 1;
+            } else {
+                self.truncated = true;
+            }
+            padded = false;
+            if let Some(slot) = destination.slot(&mut self.buffer, pos) {
+                *slot = xfer[0];
                 pos += 1;
+                padded = xfer[0] == 0x15;
+            } else {
+                self.truncated = true;
             }
         }
-        //log::info!("response {}", core::str::from_utf8(&response[0..pos]).unwrap());
-        Ok(&mut response[0..pos])
+        // an odd-length response is padded out to a whole half-word; any other
+        // 0x15 is part of the response, which may be binary socket data
+        if padded {
+            pos -= 1;
+        }
+        Ok(pos)
     }
 
     // ------------------------------------------------------------------------
     // Request handling
     // ------------------------------------------------------------------------
 
-    pub(crate) fn join(&mut self, join_info: &JoinInfo) -> Result<(), JoinError> {
-        self.process_backlog();
+    pub(crate) fn join(&mut self, join_info: &JoinInfo, ip_config: &IpConfig) -> Result<JoinResult, ArbiterError<Spi, ChipSelectPin>> {
+        self.ensure_ready()?;
+        self.configure_ip(ip_config)?;
         match join_info {
-            JoinInfo::Open => {
-                Ok(())
+            JoinInfo::Open { ssid } => {
+                self.join_network(ssid, None, 0)
             }
             JoinInfo::Wep { ssid, password } => {
-                let mut response = [0u8; 1024];
-
-                self.send_string(
-                    &command!(U36, "CB=2"),
-                    &mut response).map_err(|_| JoinError::InvalidSsid)?;
+                self.join_network(ssid, Some(password), 1)
+            }
+            JoinInfo::Wpa { ssid, password } => {
+                self.join_network(ssid, Some(password), 2)
+            }
+            JoinInfo::Wpa2 { ssid, password } => {
+                self.join_network(ssid, Some(password), 3)
+            }
+            JoinInfo::WpaMixed { ssid, password } => {
+                self.join_network(ssid, Some(password), 4)
+            }
+        }
+    }
 
-                self.send_string(
-                    &command!(U36, "C1={}", ssid),
-                    &mut response).map_err(|_| JoinError::InvalidSsid)?;
+    fn configure_ip(&mut self, ip_config: &IpConfig) -> Result<(), ArbiterError<Spi, ChipSelectPin>> {
+        match ip_config {
+            IpConfig::Dhcp => {
+                self.send_command(&command!(U8, "C4=1"))?;
+            }
+            IpConfig::Static { ip, netmask, gateway, primary_dns, secondary_dns } => {
+                self.send_command(&command!(U8, "C4=0"))?;
+                self.send_command(&command!(U32, "C6={}", ip))?;
+                self.send_command(&command!(U32, "C7={}", netmask))?;
+                self.send_command(&command!(U32, "C8={}", gateway))?;
+                self.send_command(&command!(U32, "C9={}", primary_dns))?;
+
+                if let Some(secondary_dns) = secondary_dns {
+                    self.send_command(&command!(U32, "CA={}", secondary_dns))?;
+                }
+            }
+        }
 
-                self.send_string(
-                    &command!(U72, "C2={}", password),
-                    &mut response).map_err(|_| JoinError::InvalidPassword)?;
+        Ok(())
+    }
 
-                self.send_string(
-                    &command!(U8, "C3=4"),
-                    &mut response).map_err(|_| JoinError::Unknown)?;
+    /// Join using the module's `C3` security type:
+    /// 0 = open, 1 = WEP, 2 = WPA, 3 = WPA2-AES, 4 = WPA/WPA2 mixed.
+    fn join_network(&mut self, ssid: &str, password: Option<&str>, security: u8) -> Result<JoinResult, ArbiterError<Spi, ChipSelectPin>> {
+        self.send_command(&command!(U36, "CB=2"))?;
+        self.send_command(&command!(U36, "C1={}", ssid))?;
 
+        if let Some(password) = password {
+            self.send_command(&command!(U72, "C2={}", password))?;
+        }
 
-                let response = self.send_string(&command!(U4, "C0"), &mut response).map_err(|_| JoinError::Unknown)?;
+        self.send_command(&command!(U8, "C3={}", security))?;
 
-                log::info!( "[[{}]]", core::str::from_utf8(&response).unwrap());
+        let response = self.send_string(&command!(U4, "C0"))?;
 
-                let parse_result = parser::join_response(&response);
+        log::info!("[[{:?}]]", core::str::from_utf8(response));
 
-                log::info!("response for JOIN {:?}", parse_result);
+        let parse_result = parser::join_response(response);
 
-                self.process_backlog();
+        log::info!("response for JOIN {:?}", parse_result);
 
-                match parse_result {
-                    Ok((_, response)) => {
-                        match response {
-                            JoinResponse::Ok => {
-                                Ok(())
-                            }
-                            JoinResponse::JoinError => {
-                                Err(JoinError::UnableToAssociate)
-                            }
-                        }
+        match parse_result {
+            Ok((_, join)) => {
+                match join {
+                    JoinResponse::Ok(result) => {
+                        Ok(result)
                     }
-                    Err(_) => {
-                        log::info!( "{:?}", &response);
-                        Err(JoinError::UnableToAssociate)
+                    JoinResponse::JoinError => {
+                        Err(Error::UnableToAssociate(parser::module_error(response)))
                     }
                 }
             }
+            Err(_) => {
+                log::info!( "{:?}", &response);
+                Err(Error::UnexpectedResponse)
+            }
         }
     }
 
-    pub(crate) fn connect(&mut self, proto: IpProtocol, socket_num: usize, remote: HostSocketAddr) -> Result<(), ConnectError> {
-        self.process_backlog();
-        log::info!("CONNECT {:?} {:?}", proto, remote);
+    pub(crate) fn leave(&mut self) -> Result<(), ArbiterError<Spi, ChipSelectPin>> {
+        self.ensure_ready()?;
+        self.send_command(&command!(U4, "CD"))
+    }
 
-        let mut response = [0u8; 1024];
+    /// Start the soft access point: `AS` (SSID), `A1` (security), `A2` (passphrase),
+    /// `AC` (channel), `AT` (max clients), `C6`/`C7` (own address), `AL` (DHCP pool),
+    /// then `A0` to activate.
+    pub(crate) fn start_access_point(&mut self, config: &AccessPointConfig) -> Result<(), ArbiterError<Spi, ChipSelectPin>> {
+        self.ensure_ready()?;
 
-        self.send_string(
-            &command!( U8, "P0={}", socket_num),
-            &mut response).map_err(|e| ConnectError::SpiError(e))?;
+        self.send_command(&command!(U40, "AS=0,{}", config.ssid))?;
 
-        match proto {
-            IpProtocol::Tcp => {
-                self.send_string(
-                    &command!(U8,"P1=0"),
-                    &mut response).map_err(|e| ConnectError::SpiError(e))?;
+        let (security, password) = match config.security {
+            AccessPointSecurity::Open => (0, None),
+            AccessPointSecurity::Wpa2 { password } => (3, Some(password)),
+            AccessPointSecurity::WpaMixed { password } => (4, Some(password)),
+        };
+
+        self.send_command(&command!(U8, "A1={}", security))?;
+
+        if let Some(password) = password {
+            self.send_command(&command!(U72, "A2={}", password))?;
+        }
+
+        self.send_command(&command!(U8, "AC={}", config.channel))?;
+        self.send_command(&command!(U8, "AT={}", config.max_clients))?;
+        self.send_command(&command!(U32, "C6={}", config.ip))?;
+        self.send_command(&command!(U32, "C7={}", config.netmask))?;
+        self.send_command(&command!(U40, "AL={},{}", config.dhcp_start, config.dhcp_end))?;
+        self.send_command(&command!(U4, "A0"))?;
+
+        log::info!("eS-WiFi access point {} started", config.ssid);
+        Ok(())
+    }
+
+    pub(crate) fn stop_access_point(&mut self) -> Result<(), ArbiterError<Spi, ChipSelectPin>> {
+        self.ensure_ready()?;
+        self.send_command(&command!(U4, "AE"))
+    }
+
+    pub(crate) fn access_point_stations<N: ArrayLength<Station>>(&mut self, stations: &mut Vec<Station, N>) -> Result<(), ArbiterError<Spi, ChipSelectPin>> {
+        self.ensure_ready()?;
+        // heapless 0.5 `Vec::clear()` indexes past the end of a non-empty
+        // vector, which debug builds reject
+        *stations = Vec::new();
+
+        let response = self.send_string(&command!(U4, "AR"))?;
+
+        let (mut input, _) = parser::crlf(response).map_err(|_| Error::UnexpectedResponse)?;
+
+        while let Ok((remainder, station)) = parser::station(input) {
+            input = remainder;
+            if stations.push(station).is_err() {
+                return Ok(());
             }
-            IpProtocol::Udp => {
-                self.send_string(
-                    &command!(U8,"P1=1"),
-                    &mut response).map_err(|e| ConnectError::SpiError(e))?;
+        }
+
+        match parser::list_end(input) {
+            Ok((_, ListResponse::Ok)) => Ok(()),
+            Ok((_, ListResponse::Error)) => Err(Error::Module(parser::module_error(input))),
+            Err(_) => Err(Error::UnexpectedResponse),
+        }
+    }
+
+    pub(crate) fn scan<N: ArrayLength<ScanResult>>(&mut self, results: &mut Vec<ScanResult, N>) -> Result<(), ArbiterError<Spi, ChipSelectPin>> {
+        self.ensure_ready()?;
+        // see `access_point_stations()`
+        *results = Vec::new();
+
+        let response = self.send_string(&command!(U4, "F0"))?;
+
+        let (mut input, _) = parser::crlf(response).map_err(|_| Error::UnexpectedResponse)?;
+
+        while let Ok((remainder, result)) = parser::scan_result(input) {
+            input = remainder;
+            if results.push(result).is_err() {
+                return Ok(());
             }
         }
 
-        self.send_string(
-            &command!(U32, "P3={}", remote.addr().ip()),
-            &mut response).map_err(|e| ConnectError::SpiError(e))?;
+        let result = match parser::list_end(input) {
+            Ok((_, ListResponse::Ok)) => Ok(()),
+            Ok((_, ListResponse::Error)) => Err(Error::Module(parser::module_error(input))),
+            Err(_) => Err(Error::UnexpectedResponse),
+        };
+
+        // the results which fit are kept
+        match result {
+            Err(Error::UnexpectedResponse) if self.truncated => Err(Error::ResponseTruncated),
+            result => result,
+        }
+    }
+
+    pub(crate) fn network_status(&mut self) -> Result<NetworkStatus, ArbiterError<Spi, ChipSelectPin>> {
+        self.ensure_ready()?;
 
-        self.send_string(
-            &command!(U32, "P4={}", remote.port()),
-            &mut response).map_err(|e| ConnectError::SpiError(e))?;
+        let response = self.send_string(&command!(U4, "C?"))?;
+        let (_, settings) = parser::network_settings(response).map_err(|_| Error::UnexpectedResponse)?;
 
-        let response = self.send_string(&command!(U8, "P6=1"), &mut response).map_err(|e| ConnectError::SpiError(e))?;
+        let response = self.send_string(&command!(U4, "CR"))?;
+        let (_, rssi) = parser::rssi_response(response).map_err(|_| Error::UnexpectedResponse)?;
 
-        if let Ok((_, ConnectResponse::Ok)) = parser::connect_response(&response) {
-            Ok(())
-        } else {
-            Err(ConnectError::ConnectionFailed)
+        let response = self.send_string(&command!(U4, "Z5"))?;
+        let (_, mac) = parser::mac_response(response).map_err(|_| Error::UnexpectedResponse)?;
+
+        Ok(NetworkStatus {
+            ssid: settings.ssid,
+            ip: settings.ip,
+            netmask: settings.netmask,
+            gateway: settings.gateway,
+            primary_dns: settings.primary_dns,
+            secondary_dns: settings.secondary_dns,
+            mac,
+            rssi,
+        })
+    }
+
+    /// Estimate the channel of the joined access point from a scan. Of
+    /// several sharing its name, the one heard nearest the joined RSSI is
+    /// taken to be it.
+    pub(crate) fn estimate_channel(&mut self) -> Result<Option<u8>, ArbiterError<Spi, ChipSelectPin>> {
+        self.ensure_ready()?;
+
+        let response = self.send_string(&command!(U4, "C?"))?;
+        let (_, settings) = parser::network_settings(response).map_err(|_| Error::UnexpectedResponse)?;
+        if settings.ssid.is_empty() {
+            return Ok(None);
+        }
+
+        let response = self.send_string(&command!(U4, "CR"))?;
+        let (_, rssi) = parser::rssi_response(response).map_err(|_| Error::UnexpectedResponse)?;
+
+        let response = self.send_string(&command!(U4, "F0"))?;
+        let (mut input, _) = parser::crlf(response).map_err(|_| Error::UnexpectedResponse)?;
+
+        let mut nearest: Option<(i32, u8)> = None;
+        while let Ok((remainder, result)) = parser::scan_result(input) {
+            input = remainder;
+            let distance = (i32::from(result.rssi) - i32::from(rssi)).abs();
+            if result.ssid == settings.ssid && nearest.is_none_or(|(nearest, _)| distance < nearest) {
+                nearest = Some((distance, result.channel));
+            }
+        }
+        let channel = nearest.map(|(_, channel)| channel);
+
+        let result = match parser::list_end(input) {
+            Ok((_, ListResponse::Ok)) => Ok(channel),
+            Ok((_, ListResponse::Error)) => Err(Error::Module(parser::module_error(input))),
+            Err(_) => Err(Error::UnexpectedResponse),
+        };
+
+        // the access point may have been among the results which fit
+        match result {
+            Err(Error::UnexpectedResponse) if self.truncated => channel.map(Some).ok_or(Error::ResponseTruncated),
+            result => result,
         }
     }
 
-    pub(crate) fn close(&mut self, socket_num: usize) -> Result<(), CloseError> {
-        self.process_backlog();
-        let mut response = [0u8; 1024];
+    pub(crate) fn resolve(&mut self, hostname: &str) -> Result<IpAddr, ArbiterError<Spi, ChipSelectPin>> {
+        self.ensure_ready()?;
+
+        let response = self.send_string(
+            &command!(U260, "D0={}", hostname))?;
+
+        match parser::resolve_response(response) {
+            Ok((_, ResolveResponse::Ok(ip))) => {
+                Ok(ip)
+            }
+            Ok((_, ResolveResponse::Error(reason))) => {
+                let is_timeout = reason
+                    .windows(7)
+                    .any(|w| w.eq_ignore_ascii_case(b"timeout"));
+                if is_timeout {
+                    Err(Error::DnsTimeout)
+                } else {
+                    Err(Error::NoSuchHost(parser::module_error(response)))
+                }
+            }
+            Err(_) => {
+                Err(Error::UnexpectedResponse)
+            }
+        }
+    }
 
-        self.send_string(
-            &command!( U8, "P0={}", socket_num),
-            &mut response).map_err(|e| CloseError::SpiError(e))?;
+    pub(crate) fn connect(&mut self, proto: IpProtocol, socket_num: usize, remote: HostSocketAddr) -> Result<(), ArbiterError<Spi, ChipSelectPin>> {
+        self.process_backlog(socket_num)?;
+        // the module reports a peer closing TCP connections only
+        let stream = !matches!(proto, IpProtocol::Udp);
+        self.open_connection(proto, remote)?;
+        self.connected[socket_num] = stream;
+        Ok(())
+    }
 
-        let response = self.send_string(&command!(U8, "P6=0"), &mut response).map_err(|e| CloseError::SpiError(e))?;
+    /// Connect the selected socket.
+    fn open_connection(&mut self, proto: IpProtocol, remote: HostSocketAddr) -> Result<(), ArbiterError<Spi, ChipSelectPin>> {
+        log::info!("CONNECT {:?} {:?}", proto, remote);
 
-        //let response = parser::close_response(&response);
+        match proto {
+            IpProtocol::Tcp => {
+                self.send_command(&command!(U8,"P1=0"))?;
+            }
+            IpProtocol::Udp => {
+                self.send_command(&command!(U8,"P1=1"))?;
+            }
+            IpProtocol::Tls => {
+                self.send_command(&command!(U8,"P1=3"))?;
+            }
+        }
 
-        //log::info!("close resp {:?}", response);
+        self.send_command(&command!(U32, "P3={}", remote.addr().ip()))?;
+        self.send_command(&command!(U32, "P4={}", remote.port()))?;
 
-        //Ok(())
+        let response = self.send_string(&command!(U8, "P6=1"))?;
 
-        if let Ok((_, CloseResponse::Ok)) = parser::close_response(&response) {
-            Ok(())
-        } else {
-            Err(CloseError::Error)
+        match parser::connect_response(response) {
+            Ok((_, ConnectResponse::Ok)) => {
+                Ok(())
+            }
+            Ok((_, ConnectResponse::Error(reason))) if matches!(proto, IpProtocol::Tls) && parser::is_tls_failure(reason) => {
+                Err(Error::TlsHandshakeFailed(parser::module_error(response)))
+            }
+            Ok((_, ConnectResponse::Error(_))) => {
+                Err(Error::ConnectionFailed(parser::module_error(response)))
+            }
+            Err(_) => {
+                Err(Error::UnexpectedResponse)
+            }
         }
     }
 
-    pub(crate) fn write(&mut self, socket_num: usize, buf: &[u8]) -> Result<usize, WriteError> {
-        self.process_backlog();
+    /// Connect a TLS client socket, using the certificates in `tls.slot`.
+    pub(crate) fn connect_tls(&mut self, socket_num: usize, remote: HostSocketAddr, tls: &TlsConfig) -> Result<(), ArbiterError<Spi, ChipSelectPin>> {
+        self.process_backlog(socket_num)?;
+
+        // 0 = no verification, 2 = peer certificate required
+        self.send_command(&command!(U8, "P9={}", if tls.verify_server { 2 } else { 0 }))?;
+        self.send_command(&command!(U8, "PF={}", tls.slot))?;
+
+        self.open_connection(IpProtocol::Tls, remote)?;
+        self.connected[socket_num] = true;
+        Ok(())
+    }
+
+    /// Program a certificate or key into one of the module's certificate slots.
+    pub(crate) fn load_certificate(&mut self, kind: CertificateKind, slot: u8, data: &[u8]) -> Result<(), ArbiterError<Spi, ChipSelectPin>> {
+        self.ensure_ready()?;
+
+        let command = command!(U32, "PG={},{},{}", kind as u8, slot, data.len());
 
-        let mut len = buf.len();
-        if len > 1046 {
-            len = 1046
+        let response = self.send_with_payload(command.as_bytes(), data)?;
+
+        match parser::command_response(response) {
+            Ok((_, CommandResponse::Ok)) => Ok(()),
+            Ok((_, CommandResponse::Error)) => Err(Error::Module(parser::module_error(response))),
+            Err(_) => Err(Error::UnexpectedResponse),
         }
+    }
 
-        let mut response = [0u8; 1024];
+    pub(crate) fn listen(&mut self, socket_num: usize, port: u16, backlog: u8) -> Result<(), ArbiterError<Spi, ChipSelectPin>> {
+        self.process_backlog(socket_num)?;
+        log::info!("LISTEN {} {}", socket_num, port);
 
-        let command = command!(U8, "P0={}", socket_num);
-        self.send(command.as_bytes(), &mut response);
+        self.send_command(&command!(U8, "P1=0"))?;
+        self.send_command(&command!(U16, "P2={}", port))?;
+        self.send_command(&command!(U8, "P8={}", backlog))?;
+        self.send_command(&command!(U8, "P5=1"))?;
+        self.listening[socket_num] = true;
+        Ok(())
+    }
 
-        self.send_string(
-            &command!(U8, "P0={}", socket_num),
-            &mut response,
-        ).map_err(|e| WriteError::SpiError(e))?;
+    /// Check the module for events, unless it is yet to be initialized.
+    pub(crate) fn poll(&mut self) {
+        if matches!(self.state, State::Ready) {
+            self.drain_messages();
+        }
+    }
 
-        self.send_string(
-            &command!(U16, "S1={}", len),
-            &mut response,
-        ).map_err(|e| WriteError::SpiError(e))?;
+    /// Stop a server socket. If that fails, the module is reset, as for `close()`.
+    pub(crate) fn stop_listening(&mut self, socket_num: usize) -> Result<(), ArbiterError<Spi, ChipSelectPin>> {
+        let result = self.process_backlog(socket_num)
+            .and_then(|_| self.send_command(&command!(U8, "P5=0")));
+        self.listening[socket_num] = false;
+        self.recover_from(result)
+    }
 
+    /// Close a socket. If that fails, the module may still hold it open, so
+    /// it is reset on the next request rather than leaking the socket.
+    pub(crate) fn close(&mut self, socket_num: usize) -> Result<(), ArbiterError<Spi, ChipSelectPin>> {
+        let result = self.close_socket(socket_num);
+        self.recover_from(result)
+    }
 
-        // to ensure it's an even number of bytes, abscond with 1 byte from the payload.
-        let prefix = [b'S', b'0', b'\r', buf[0]];
-        let remainder = &buf[1..len];
+    fn close_socket(&mut self, socket_num: usize) -> Result<(), ArbiterError<Spi, ChipSelectPin>> {
+        self.process_backlog(socket_num)?;
+        // a close that fails resets the module, which loses it anyway
+        self.connected[socket_num] = false;
 
-        self.await_data_ready();
-        {
-            let _cs = self.cs.select();
-
-            for chunk in prefix.chunks(2) {
-                let mut xfer: [u8; 2] = [0; 2];
-                xfer[1] = chunk[0];
-                xfer[0] = chunk[1];
-                //if chunk.len() == 2 {
-                //} else {
-                //xfer[0] = 0x0A
-                //}
-
-                //log::info!("transfer {:?}", xfer);
-                self.spi.transfer(&mut xfer);
-            }
-
-            for chunk in remainder.chunks(2) {
-                let mut xfer: [u8; 2] = [0; 2];
-                xfer[1] = chunk[0];
-                if chunk.len() == 2 {
-                    xfer[0] = chunk[1]
-                } else {
-                    xfer[0] = 0x0A
-                }
+        let response = self.send_string(&command!(U8, "P6=0"))?;
 
-                //log::info!("transfer {:?}", xfer);
-                self.spi.transfer(&mut xfer);
-            }
+        match parser::close_response(response) {
+            Ok((_, CloseResponse::Ok)) => Ok(()),
+            Ok((_, CloseResponse::Error)) => Err(Error::Module(parser::module_error(response))),
+            Err(_) => Err(Error::UnexpectedResponse),
         }
-        self.await_data_ready();
-        let response = self.receive(&mut response).map_err(|e| WriteError::SpiError(e))?;
+    }
 
-        if let Ok((_, WriteResponse::Ok(len))) = parser::write_response(response) {
-            Ok(len)
-        } else {
-            Err(WriteError::Error)
+    /// Arrange a reset of a ready module after a failed `result`.
+    fn recover_from(&mut self, result: Result<(), ArbiterError<Spi, ChipSelectPin>>) -> Result<(), ArbiterError<Spi, ChipSelectPin>> {
+        if result.is_err() && matches!(self.state, State::Ready) {
+            self.recover();
         }
+        result
     }
 
-    pub(crate) fn read(&mut self, socket_num: usize, buffer: &mut [u8]) -> Result<usize, ReadError> {
-        self.process_backlog();
-        let mut pos = 0;
-        let buf_len = buffer.len();
-        loop {
-            let result = self.read_internal(socket_num, &mut buffer[pos..buf_len]);
-            match result {
+    /// Write as much of `buf` as the module takes, in as many `S0` writes as
+    /// needed. Stops early once the module accepts less than it was given.
+    ///
+    /// The module waits up to `wait` milliseconds for room to send each `S0`.
+    pub(crate) fn write(&mut self, socket_num: usize, buf: &[u8], wait: u32) -> Result<usize, ArbiterError<Spi, ChipSelectPin>> {
+        self.process_backlog(socket_num)?;
+        let mut written = 0;
+        for chunk in buf.chunks(MAX_WRITE_LEN) {
+            match self.write_internal(socket_num, chunk, wait) {
                 Ok(len) => {
-                    pos += len;
-                    if len == 0 || pos == buffer.len() {
-                        return Ok(pos);
-                    }
-                }
-                Err(e) => {
-                    if pos == 0 {
-                        return Err(e);
-                    } else {
-                        return Ok(pos);
+                    written += len;
+                    if len < chunk.len() {
+                        break;
                     }
                 }
+                Err(e) if written == 0 => return Err(e),
+                Err(_) => break,
             }
         }
+        Ok(written)
+    }
+
+    /// Write `buf` in a single `S0`, as a UDP datagram must be.
+    pub(crate) fn write_datagram(&mut self, socket_num: usize, buf: &[u8], wait: u32) -> Result<usize, ArbiterError<Spi, ChipSelectPin>> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if buf.len() > MAX_WRITE_LEN {
+            return Err(Error::DatagramTooLarge);
+        }
+
+        self.process_backlog(socket_num)?;
+        self.write_internal(socket_num, buf, wait)
     }
 
-    fn read_internal(&mut self, socket_num: usize, buffer: &mut [u8]) -> Result<usize, ReadError> {
-        self.process_backlog();
+    fn write_internal(&mut self, socket_num: usize, buf: &[u8], wait: u32) -> Result<usize, ArbiterError<Spi, ChipSelectPin>> {
+        self.select_socket(socket_num)?;
 
-        let mut response = [0u8; 1100];
+        self.set(Setting::WriteLength, buf.len() as u32)?;
+        self.set(Setting::WriteWait, wait.clamp(MIN_SOCKET_WAIT, MAX_SOCKET_WAIT))?;
 
-        self.send_string(
-            &command!( U8, "P0={}", socket_num),
-            &mut response,
-        ).map_err(|e| ReadError::SpiError(e))?;
+        let response = self.send_with_payload(b"S0\r", buf)?;
 
-        let mut len = buffer.len();
-        if len > 1460 {
-            len = 1460;
+        match parser::write_response(response) {
+            Ok((_, WriteResponse::Ok(len))) if len <= buf.len() => Ok(len),
+            Ok((_, WriteResponse::Ok(_))) => Err(Error::UnexpectedResponse),
+            Ok((_, WriteResponse::Error)) => Err(Error::WriteFailed(parser::module_error(response))),
+            Err(_) => Err(Error::UnexpectedResponse),
         }
+    }
 
-        self.send_string(
-            &command!( U16, "R1={}", len),
-            &mut response,
-        ).map_err(|e| ReadError::SpiError(e))?;
+    /// Read what the module holds for `socket_num`, up to a single module
+    /// read, waiting up to `wait` milliseconds for it. A stream read returns
+    /// as soon as some data has arrived, and a datagram read takes exactly
+    /// one datagram, so each request sets the read wait only once.
+    pub(crate) fn read(&mut self, socket_num: usize, buffer: &mut [u8], wait: u32) -> Result<usize, ArbiterError<Spi, ChipSelectPin>> {
+        self.process_backlog(socket_num)?;
+        self.read_internal(socket_num, buffer, wait)
+    }
+
+    fn read_internal(&mut self, socket_num: usize, buffer: &mut [u8], wait: u32) -> Result<usize, ArbiterError<Spi, ChipSelectPin>> {
+        self.select_socket(socket_num)?;
 
-        self.send_string(
-            &command!(U8, "R2=15"),
-            &mut response,
-        ).map_err(|e| ReadError::SpiError(e))?;
+        let len = buffer.len().min(MAX_READ_LEN);
 
-        self.send_string(
-            &command!(U8, "R3=1"),
-            &mut response,
-        ).map_err(|e| ReadError::SpiError(e))?;
+        self.set(Setting::ReadLength, len as u32)?;
+        self.set(Setting::ReadWait, wait.clamp(MIN_SOCKET_WAIT, MAX_SOCKET_WAIT))?;
+        self.set(Setting::ReadMode, 1)?;
 
         //self.send("R?\r".as_bytes(), &mut response);
 
-        self.await_data_ready();
+        self.await_data_ready()?;
         {
-            let _cs = self.cs.select();
+            let _cs = self.cs.select().map_err(Error::Pin)?;
 
             let mut xfer = [b'0', b'R'];
-            self.spi.transfer(&mut xfer);
+            self.spi.transfer(&mut xfer).map_err(Error::Spi)?;
 
             xfer = [b'\n', b'\r'];
-            self.spi.transfer(&mut xfer);
+            self.spi.transfer(&mut xfer).map_err(Error::Spi)?;
         }
 
-        self.await_data_ready();
-
-        let response = self.receive(&mut response).map_err(|e| ReadError::SpiError(e))?;
+        // the payload lands directly in `buffer`, after the leading `\r\n`;
+        // what follows it spills into `overflow`, which also has room for
+        // an error message longer than the buffer
+        let mut header = [0u8; 2];
+        let mut overflow = [0u8; 64];
+        let mut destination = Destination::Parts([&mut header, &mut buffer[..len], &mut overflow]);
+        let received = self.receive_into(&mut destination)?;
+
+        // the header and trailer alone must parse as an empty read
+        if received >= parser::READ_FRAMING {
+            let mut edges = [0u8; parser::READ_FRAMING];
+            let trailer_start = received - parser::READ_TRAILER.len();
+            for (i, edge) in edges.iter_mut().enumerate() {
+                let pos = if i < 2 { i } else { trailer_start + i - 2 };
+                *edge = destination.get(pos).unwrap_or_default();
+            }
+            if let Ok((_, ReadResponse::Ok(b""))) = parser::read_response(&edges) {
+                let read = received - parser::READ_FRAMING;
+                // a bare -1 is the module reporting a failed read, parsed below
+                let failed = read == 2 && destination.get(2) == Some(b'-') && destination.get(3) == Some(b'1');
+                if !failed {
+                    return if read <= len { Ok(read) } else { Err(Error::UnexpectedResponse) };
+                }
+            }
+        }
 
-        if let Ok((_, ReadResponse::Ok(data))) = parser::read_response(&response) {
-            for (i, b) in data.iter().enumerate() {
-                buffer[i] = *b;
+        // anything else is short, so reassemble what there is of it to parse
+        let mut response: Vec<u8, U128> = Vec::new();
+        for pos in 0..received {
+            if let Some(b) = destination.get(pos) {
+                if response.push(b).is_err() {
+                    break;
+                }
             }
-            return Ok(data.len());
         }
-        //result
-        Err(ReadError::Error)
+
+        match parser::read_response(&response) {
+            Ok((_, ReadResponse::Err)) => Err(Error::ReadFailed(parser::module_error(&response))),
+            _ => Err(Error::UnexpectedResponse),
+        }
     }
 }
 
+/// Where a response is clocked out to.
+enum Destination<'a> {
+    /// The arbiter's own buffer.
+    Scratch,
+    /// Consecutive slices, filled in order.
+    Parts([&'a mut [u8]; 3]),
+}
+
+impl<'a> Destination<'a> {
+    /// Where the byte at `pos` of the response goes, if it fits.
+    fn slot<'b>(&'b mut self, scratch: &'b mut [u8], pos: usize) -> Option<&'b mut u8> {
+        match self {
+            Destination::Scratch => scratch.get_mut(pos),
+            Destination::Parts(parts) => {
+                let mut pos = pos;
+                for part in parts.iter_mut() {
+                    if pos < part.len() {
+                        return part.get_mut(pos);
+                    }
+                    pos -= part.len();
+                }
+                None
+            }
+        }
+    }
+
+    /// The byte at `pos` of a response clocked out to `Parts`.
+    fn get(&self, pos: usize) -> Option<u8> {
+        match self {
+            Destination::Scratch => None,
+            Destination::Parts(parts) => {
+                let mut pos = pos;
+                for part in parts.iter() {
+                    if pos < part.len() {
+                        return Some(part[pos]);
+                    }
+                    pos -= part.len();
+                }
+                None
+            }
+        }
+    }
+}
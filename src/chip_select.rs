@@ -1,6 +1,6 @@
 use embedded_hal::digital::v2::OutputPin;
 use drogue_embedded_timer::Delay;
-use embedded_time::duration::Milliseconds;
+use embedded_time::duration::Microseconds;
 
 pub(crate) struct ChipSelect<'clock, Pin, Clock>
     where Pin: OutputPin,
@@ -8,31 +8,37 @@ pub(crate) struct ChipSelect<'clock, Pin, Clock>
 {
     pin: Pin,
     delay: Delay<'clock, Clock>,
+    setup: Microseconds<u32>,
+    hold: Microseconds<u32>,
 }
 
 impl<'clock, Pin, Clock> ChipSelect<'clock, Pin, Clock>
     where Pin: OutputPin,
           Clock: embedded_time::Clock,
 {
-    /// Construct a new CS pin controller and set it high (unselected)
-    pub(crate) fn new(mut pin: Pin, delay: Delay<'clock, Clock>) -> Self {
-        pin.set_high();
-        Self {
+    /// Construct a new CS pin controller and set it high (unselected).
+    /// The pin is held low for `setup` before each transfer, and high for
+    /// `hold` after it.
+    pub(crate) fn new(mut pin: Pin, delay: Delay<'clock, Clock>, setup: Microseconds<u32>, hold: Microseconds<u32>) -> Result<Self, Pin::Error> {
+        pin.set_high()?;
+        Ok(Self {
             pin,
             delay,
-        }
+            setup,
+            hold,
+        })
     }
 
-    pub(crate) fn select<'pin>(&'pin mut self) -> Selected<'pin, 'clock, Pin, Clock> {
+    pub(crate) fn select<'pin>(&'pin mut self) -> Result<Selected<'pin, 'clock, Pin, Clock>, Pin::Error> {
         Selected::new(self)
     }
 
-    fn set_low(&mut self) {
-        self.pin.set_low();
+    fn set_low(&mut self) -> Result<(), Pin::Error> {
+        self.pin.set_low()
     }
 
-    fn set_high(&mut self) {
-        self.pin.set_high();
+    fn set_high(&mut self) -> Result<(), Pin::Error> {
+        self.pin.set_high()
     }
 }
 
@@ -47,12 +53,12 @@ impl<'pin, 'clock, Pin, Clock> Selected<'pin, 'clock, Pin, Clock>
     where Pin: OutputPin,
           Clock: embedded_time::Clock,
 {
-    fn new(cs: &'pin mut ChipSelect<'clock, Pin, Clock>) -> Self {
-        cs.set_low();
-        cs.delay.delay( Milliseconds(10u32));
-        Self {
+    fn new(cs: &'pin mut ChipSelect<'clock, Pin, Clock>) -> Result<Self, Pin::Error> {
+        cs.set_low()?;
+        cs.delay.delay(cs.setup);
+        Ok(Self {
             cs
-        }
+        })
     }
 }
 
@@ -61,7 +67,8 @@ impl<Pin, Clock> Drop for Selected<'_, '_, Pin, Clock>
           Clock: embedded_time::Clock,
 {
     fn drop(&mut self) {
-        self.cs.set_high();
-        self.cs.delay.delay( Milliseconds(10u32));
+        // nowhere to report a failure; the next select drives the pin again
+        self.cs.set_high().ok();
+        self.cs.delay.delay(self.cs.hold);
     }
 }
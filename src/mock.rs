@@ -0,0 +1,535 @@
+//! Host-side emulation of the eS-WiFi module, for testing without a board.
+//!
+//! A `MockModule` hands out an SPI peripheral and the four control pins, which
+//! are given to `Adapter::new()` along with a `MockClock`. It speaks the module's
+//! SPI protocol: commands arrive as byte-swapped half-words padded with `\n`,
+//! responses go out the same way padded with `0x15`, and the ready pin follows
+//! the reset, command and response phases.
+//!
+//! Every command is answered with `OK`, unless a response has been scripted for it
+//! with `respond()` or `respond_once()`. Socket writes and reads are emulated on top
+//! of that: data written through `S0` is collected for `written()`, and data queued
+//! with `receive_data()` is handed out by `R0`, honouring the `R1` read size.
+//! Unsolicited messages queued with `message()` are delivered through `MR`.
+//! Each socket's connection is tracked from `P3`, `P4`, `P5` and `P6`, and
+//! reported by `P?`; `close_peer()` and `accept_client()` change it as a peer
+//! would, along with the message the module prints. Commands answered by a
+//! scripted response are not tracked.
+//!
+//! ```ignore
+//! let module = MockModule::new();
+//! let clock = MockClock::new();
+//! module.respond("C0", b"\r\n[JOIN   ] drogue,192.168.1.174,0,0\r\nOK\r\n> ");
+//!
+//! let mut wifi = Adapter::new(
+//!     module.spi(), module.cs(), module.ready(), module.wakeup(), module.reset(), &clock,
+//!     AdapterConfig::default(),
+//! ).unwrap();
+//! wifi.join_wpa2("drogue", "rodneygnome").unwrap();
+//! assert!(module.sent("C1=drogue"));
+//! ```
+
+use core::cell::{Cell, RefCell};
+use core::fmt::Write;
+
+use embedded_hal::blocking::spi::Transfer;
+use embedded_hal::digital::v2::{InputPin, OutputPin};
+use embedded_time::fraction::Fraction;
+use embedded_time::Instant;
+use heapless::{consts::*, spsc::Queue, String, Vec};
+
+const BANNER: &[u8] = b"\r\n> ";
+const OK: &[u8] = b"\r\nOK\r\n> ";
+const NO_MESSAGE: &[u8] = b"\r\n[SOMA][EOMA]\r\nOK\r\n> ";
+const CLOSED: &[u8] = b"\r\n\r\nOK\r\n> ";
+
+/// A failure injected with `MockModule::fail_transfers()`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct MockError;
+
+enum Phase {
+    /// Held in reset, or not yet reset.
+    Off,
+    /// Collecting a command while chip-select is low.
+    Command,
+    /// Clocking out a response.
+    Response,
+}
+
+struct Rule {
+    command: String<U64>,
+    response: Vec<u8, U2048>,
+    once: bool,
+}
+
+#[derive(Default)]
+struct Socket {
+    connected: bool,
+    serving: bool,
+    ip: String<U16>,
+    port: u16,
+}
+
+struct Module {
+    phase: Phase,
+    selected: bool,
+    received: Vec<u8, U4096>,
+    response: Vec<u8, U4096>,
+    position: usize,
+    rules: Vec<Rule, U16>,
+    messages: Queue<String<U128>, U8>,
+    sent: Queue<String<U64>, U64>,
+    inbound: Vec<u8, U4096>,
+    written: Vec<u8, U4096>,
+    write_len: usize,
+    read_len: usize,
+    fail_transfers: bool,
+    hung: bool,
+    /// The socket selected with `P0`.
+    socket: usize,
+    sockets: [Socket; 4],
+}
+
+/// An emulated eS-WiFi module.
+pub struct MockModule {
+    module: RefCell<Module>,
+}
+
+impl Default for MockModule {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MockModule {
+    pub fn new() -> Self {
+        Self {
+            module: RefCell::new(Module {
+                phase: Phase::Off,
+                selected: false,
+                received: Vec::new(),
+                response: Vec::new(),
+                position: 0,
+                rules: Vec::new(),
+                messages: Queue::new(),
+                sent: Queue::new(),
+                inbound: Vec::new(),
+                written: Vec::new(),
+                write_len: 0,
+                read_len: 0,
+                fail_transfers: false,
+                hung: false,
+                socket: 0,
+                sockets: Default::default(),
+            }),
+        }
+    }
+
+    /// The SPI peripheral the module is attached to.
+    pub fn spi(&self) -> MockSpi<'_> {
+        MockSpi { module: self }
+    }
+
+    pub fn cs(&self) -> MockPin<'_> {
+        MockPin { module: self, role: Role::ChipSelect }
+    }
+
+    pub fn ready(&self) -> MockPin<'_> {
+        MockPin { module: self, role: Role::Ready }
+    }
+
+    pub fn wakeup(&self) -> MockPin<'_> {
+        MockPin { module: self, role: Role::Wakeup }
+    }
+
+    pub fn reset(&self) -> MockPin<'_> {
+        MockPin { module: self, role: Role::Reset }
+    }
+
+    /// Answer every command starting with `command` with `response`, which
+    /// should include the module's framing, such as `"\r\nOK\r\n> "`.
+    pub fn respond(&self, command: &str, response: &[u8]) {
+        self.add_rule(command, response, false);
+    }
+
+    /// Answer the next command starting with `command` with `response`,
+    /// ahead of any response set by `respond()`.
+    pub fn respond_once(&self, command: &str, response: &[u8]) {
+        self.add_rule(command, response, true);
+    }
+
+    /// Queue an unsolicited message, such as `"[TCP  RC] Connection closed"`.
+    pub fn message(&self, message: &str) {
+        let mut text = String::new();
+        text.push_str(message).expect("message too long");
+        self.module.borrow_mut().messages.enqueue(text).expect("too many messages");
+    }
+
+    /// Queue data arriving from the peer, to be read with `R0`.
+    pub fn receive_data(&self, data: &[u8]) {
+        self.module.borrow_mut().inbound.extend_from_slice(data).expect("too much inbound data");
+    }
+
+    /// Everything written with `S0` so far.
+    pub fn written(&self) -> Vec<u8, U4096> {
+        self.module.borrow().written.clone()
+    }
+
+    /// Whether a command starting with `command` was sent since the last
+    /// `clear_sent()`. Only the most recent 64 commands are remembered.
+    pub fn sent(&self, command: &str) -> bool {
+        self.module.borrow().sent.iter().any(|sent| sent.starts_with(command))
+    }
+
+    /// How many commands starting with `command` were sent since the last
+    /// `clear_sent()`, within the same most recent 64.
+    pub fn sent_count(&self, command: &str) -> usize {
+        self.module.borrow().sent.iter().filter(|sent| sent.starts_with(command)).count()
+    }
+
+    /// Whether commands starting with each of `commands` were sent in that
+    /// order since the last `clear_sent()`, possibly with others in between.
+    pub fn sent_in_order(&self, commands: &[&str]) -> bool {
+        let module = self.module.borrow();
+        let mut sent = module.sent.iter();
+        commands.iter().all(|command| sent.any(|sent| sent.starts_with(command)))
+    }
+
+    pub fn clear_sent(&self) {
+        let mut module = self.module.borrow_mut();
+        while module.sent.dequeue().is_some() {}
+    }
+
+    /// Fail every SPI transfer until called with `false`.
+    pub fn fail_transfers(&self, fail: bool) {
+        self.module.borrow_mut().fail_transfers = fail;
+    }
+
+    /// Have the peer of `socket` close its connection, which `P?` then
+    /// reports, and queue the module's message about it.
+    pub fn close_peer(&self, socket: usize) {
+        self.module.borrow_mut().sockets[socket].connected = false;
+        self.message("[TCP  RC] Connection closed");
+    }
+
+    /// Have a client at `remote`, such as `"192.168.1.2:52344"`, connect to
+    /// the server on `socket`, and queue the module's message about it.
+    pub fn accept_client(&self, socket: usize, remote: &str) {
+        {
+            let mut module = self.module.borrow_mut();
+            let socket = &mut module.sockets[socket];
+            let (ip, port) = remote.split_at(remote.find(':').expect("remote without a port"));
+            socket.ip = String::new();
+            socket.ip.push_str(ip).expect("address too long");
+            socket.port = port[1..].parse().expect("invalid port");
+            socket.serving = false;
+            socket.connected = true;
+        }
+        let mut message = String::<U64>::new();
+        write!(message, "Accepted {}", remote).ok();
+        self.message(&message);
+    }
+
+    /// Stop raising the ready pin, as a hung module does, until it is reset.
+    pub fn hang(&self) {
+        self.module.borrow_mut().hung = true;
+    }
+
+    fn add_rule(&self, command: &str, response: &[u8], once: bool) {
+        let mut rule = Rule {
+            command: String::new(),
+            response: Vec::new(),
+            once,
+        };
+        rule.command.push_str(command).expect("command too long");
+        rule.response.extend_from_slice(response).expect("response too long");
+        assert!(self.module.borrow_mut().rules.push(rule).is_ok(), "too many rules");
+    }
+}
+
+impl Module {
+    fn is_ready(&self) -> bool {
+        if self.hung {
+            return false;
+        }
+        match self.phase {
+            Phase::Off => false,
+            Phase::Command => true,
+            Phase::Response => self.position < self.response.len(),
+        }
+    }
+
+    // heapless 0.5 `Vec::clear()` and `truncate()` index past the end of the
+    // vector, which debug builds reject, so vectors are replaced instead
+
+    fn reset(&mut self) {
+        self.hung = false;
+        self.socket = 0;
+        self.sockets = Default::default();
+        self.received = Vec::new();
+        self.respond(BANNER);
+    }
+
+    fn respond(&mut self, response: &[u8]) {
+        self.response = Vec::new();
+        self.response.extend_from_slice(response).expect("response too long");
+        self.position = 0;
+        self.phase = Phase::Response;
+    }
+
+    fn select(&mut self) {
+        self.selected = true;
+    }
+
+    fn deselect(&mut self) {
+        self.selected = false;
+        match self.phase {
+            Phase::Command if !self.received.is_empty() => self.execute(),
+            Phase::Response if self.position >= self.response.len() => self.phase = Phase::Command,
+            _ => {}
+        }
+    }
+
+    fn transfer(&mut self, words: &mut [u8]) {
+        for pair in words.chunks_mut(2) {
+            match self.phase {
+                Phase::Command if self.selected => {
+                    // half-words arrive with their bytes swapped
+                    for b in pair.iter().rev() {
+                        self.received.push(*b).ok();
+                    }
+                    for b in pair.iter_mut() {
+                        *b = 0x15;
+                    }
+                }
+                Phase::Response if self.selected => {
+                    let first = self.next_byte();
+                    let second = self.next_byte();
+                    pair[pair.len() - 1] = first;
+                    pair[0] = second;
+                }
+                _ => {
+                    for b in pair.iter_mut() {
+                        *b = 0x15;
+                    }
+                }
+            }
+        }
+    }
+
+    fn next_byte(&mut self) -> u8 {
+        if self.position < self.response.len() {
+            self.position += 1;
+            self.response[self.position - 1]
+        } else {
+            0x15
+        }
+    }
+
+    fn execute(&mut self) {
+        let received = core::mem::take(&mut self.received);
+        let end = received.iter().position(|b| *b == b'\r').unwrap_or_else(|| received.len());
+        let command = core::str::from_utf8(&received[..end]).unwrap_or("");
+        let payload = received.get(end + 1..).unwrap_or(&[]);
+
+        let mut logged = String::new();
+        for c in command.chars() {
+            if logged.push(c).is_err() {
+                break;
+            }
+        }
+        if self.sent.enqueue(logged.clone()).is_err() {
+            self.sent.dequeue();
+            self.sent.enqueue(logged).ok();
+        }
+
+        if let Some(len) = command.strip_prefix("S1=").and_then(|len| len.parse().ok()) {
+            self.write_len = len;
+        }
+        if let Some(len) = command.strip_prefix("R1=").and_then(|len| len.parse().ok()) {
+            self.read_len = len;
+        }
+
+        if let Some(index) = self.find_rule(command) {
+            let response = self.rules[index].response.clone();
+            if self.rules[index].once {
+                // keep the remaining rules in the order they were scripted
+                self.rules[index..].rotate_left(1);
+                self.rules.pop();
+            }
+            self.respond(&response);
+            return;
+        }
+
+        let socket = &mut self.sockets[self.socket];
+        if let Some(n) = command.strip_prefix("P0=").and_then(|n| n.parse().ok()).filter(|n| *n < 4) {
+            self.socket = n;
+        } else if let Some(ip) = command.strip_prefix("P3=") {
+            socket.ip = String::new();
+            socket.ip.push_str(ip).ok();
+        } else if let Some(port) = command.strip_prefix("P4=").and_then(|port| port.parse().ok()) {
+            socket.port = port;
+        }
+
+        match command {
+            "P5=1" => {
+                socket.serving = true;
+                self.respond(OK);
+            }
+            "P5=0" => {
+                socket.serving = false;
+                self.respond(OK);
+            }
+            "P6=1" => {
+                socket.connected = true;
+                self.respond(OK);
+            }
+            "P?" => {
+                let mut response = String::<U128>::new();
+                write!(
+                    response,
+                    "\r\n{},0,192.168.1.174,0,{},{},{},0,{}\r\nOK\r\n> ",
+                    self.socket,
+                    if socket.ip.is_empty() { "0.0.0.0" } else { socket.ip.as_str() },
+                    socket.port,
+                    socket.serving as u8,
+                    socket.connected as u8,
+                ).ok();
+                self.respond(response.as_bytes());
+            }
+            "S0" => {
+                let len = self.write_len.min(payload.len());
+                self.written.extend_from_slice(&payload[..len]).expect("too much written data");
+                let mut response = String::<U32>::new();
+                write!(response, "\r\n{}\r\nOK\r\n> ", len).ok();
+                self.respond(response.as_bytes());
+            }
+            "R0" => {
+                let len = self.read_len.min(self.inbound.len());
+                let mut response = Vec::<u8, U4096>::new();
+                response.extend_from_slice(b"\r\n").ok();
+                response.extend_from_slice(&self.inbound[..len]).ok();
+                response.extend_from_slice(b"\r\nOK\r\n> ").ok();
+                self.inbound = Vec::from_slice(&self.inbound[len..]).unwrap();
+                self.respond(&response);
+            }
+            "MR" => {
+                match self.messages.dequeue() {
+                    Some(message) => {
+                        let mut response = String::<U256>::new();
+                        write!(response, "\r\n[SOMA]{}[EOMA]\r\nOK\r\n> ", message).ok();
+                        self.respond(response.as_bytes());
+                    }
+                    None => self.respond(NO_MESSAGE),
+                }
+            }
+            "P6=0" => {
+                socket.connected = false;
+                self.respond(CLOSED);
+            }
+            _ => self.respond(OK),
+        }
+    }
+
+    fn find_rule(&self, command: &str) -> Option<usize> {
+        let matches = |rule: &Rule| command.starts_with(rule.command.as_str());
+        self.rules.iter().position(|rule| rule.once && matches(rule))
+            .or_else(|| self.rules.iter().position(|rule| !rule.once && matches(rule)))
+    }
+}
+
+/// The SPI peripheral of a `MockModule`.
+pub struct MockSpi<'m> {
+    module: &'m MockModule,
+}
+
+impl Transfer<u8> for MockSpi<'_> {
+    type Error = MockError;
+
+    fn transfer<'w>(&mut self, words: &'w mut [u8]) -> Result<&'w [u8], Self::Error> {
+        let mut module = self.module.module.borrow_mut();
+        if module.fail_transfers {
+            return Err(MockError);
+        }
+        module.transfer(words);
+        Ok(words)
+    }
+}
+
+enum Role {
+    ChipSelect,
+    Ready,
+    Wakeup,
+    Reset,
+}
+
+/// One of the control pins of a `MockModule`.
+pub struct MockPin<'m> {
+    module: &'m MockModule,
+    role: Role,
+}
+
+impl OutputPin for MockPin<'_> {
+    type Error = MockError;
+
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        let mut module = self.module.module.borrow_mut();
+        match self.role {
+            Role::ChipSelect => module.select(),
+            Role::Reset => module.phase = Phase::Off,
+            Role::Ready | Role::Wakeup => {}
+        }
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        let mut module = self.module.module.borrow_mut();
+        match self.role {
+            Role::ChipSelect => module.deselect(),
+            Role::Reset => module.reset(),
+            Role::Ready | Role::Wakeup => {}
+        }
+        Ok(())
+    }
+}
+
+impl InputPin for MockPin<'_> {
+    type Error = MockError;
+
+    fn is_high(&self) -> Result<bool, Self::Error> {
+        Ok(self.module.module.borrow().is_ready())
+    }
+
+    fn is_low(&self) -> Result<bool, Self::Error> {
+        self.is_high().map(|high| !high)
+    }
+}
+
+/// A millisecond clock which ticks every time it is read, so that delays
+/// and timeouts pass without waiting.
+pub struct MockClock {
+    now: Cell<u32>,
+}
+
+impl Default for MockClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MockClock {
+    pub fn new() -> Self {
+        Self { now: Cell::new(0) }
+    }
+}
+
+impl embedded_time::Clock for MockClock {
+    type T = u32;
+    const SCALING_FACTOR: Fraction = Fraction::new(1, 1_000);
+
+    fn try_now(&self) -> Result<Instant<Self>, embedded_time::clock::Error> {
+        let now = self.now.get();
+        self.now.set(now.wrapping_add(1));
+        Ok(Instant::new(now))
+    }
+}
@@ -1,30 +1,91 @@
-use drogue_network::tcp::{TcpStack, Mode, TcpError, TcpImplError};
-use drogue_network::addr::HostSocketAddr;
-
-use nb;
-use core::cell::RefCell;
-use crate::adapter::{Adapter, AdapterError, ReadError};
-use nb::Error;
-use crate::socket::State;
+use drogue_network::tcp::{TcpStack, Mode};
+use drogue_network::addr::{HostSocketAddr, HostAddr, IpAddr, SocketAddr};
+use drogue_network::dns::{Dns, AddrType};
+
+use core::fmt::Debug;
+use crate::adapter::{Adapter, Error, TlsConfig};
+use crate::socket::{State, copy_mode};
 use embedded_hal::blocking::spi::Transfer;
 use embedded_hal::digital::v2::{OutputPin, InputPin};
-use crate::arbiter::IpProtocol;
+use crate::arbiter::{IpProtocol, MAX_WRITE_LEN};
 use embedded_time::duration::Milliseconds;
+use heapless::{consts::*, String};
 
 #[derive(Debug)]
 pub struct TcpSocket(usize);
 
-impl<'clock, Spi, ChipSelectPin, ReadyPin, WakeupPin, ResetPin, Clock> TcpStack for Adapter<'clock, Spi, ChipSelectPin, ReadyPin, WakeupPin, ResetPin, Clock>
+impl TcpSocket {
+    /// The adapter socket number, as reported in `Event`s.
+    pub fn id(&self) -> usize {
+        self.0
+    }
+}
+
+/// A TCP server socket, from `Adapter::listen()`.
+pub struct TcpListener {
+    socket: Option<usize>,
+    port: u16,
+    backlog: u8,
+    mode: Mode,
+}
+
+#[derive(Debug)]
+pub struct UdpSocket(usize);
+
+impl UdpSocket {
+    /// The adapter socket number, as reported in `Event`s.
+    pub fn id(&self) -> usize {
+        self.0
+    }
+}
+
+/// UDP stack, mirroring the `UdpStack` trait of `drogue-network`,
+/// which the 0.2 release does not export yet.
+pub trait UdpStack {
+    /// The type returned when we create a new UDP socket
+    type UdpSocket;
+    /// The type returned when we have an error
+    type Error: core::fmt::Debug;
+
+    /// Open a new UDP socket to the given address and port.
+    fn open(&self, remote: SocketAddr, mode: Mode) -> Result<Self::UdpSocket, Self::Error>;
+
+    /// Send a datagram to the remote host.
+    fn write(&self, socket: &mut Self::UdpSocket, buffer: &[u8]) -> nb::Result<(), Self::Error>;
+
+    /// Read a datagram the remote host has sent to us.
+    fn read(&self, socket: &mut Self::UdpSocket, buffer: &mut [u8]) -> nb::Result<usize, Self::Error>;
+
+    /// Close an existing UDP socket.
+    fn close(&self, socket: Self::UdpSocket) -> Result<(), Self::Error>;
+}
+
+/// How long the module may wait for data or room to send on a socket in
+/// `mode`, in milliseconds. A `Mode::Timeout` gets its whole timeout on the
+/// first pass only, after which the timer bounds the request. The arbiter
+/// clamps the wait, so a blocking socket waits as long as it allows.
+fn socket_wait(mode: &Mode, first: bool) -> u32 {
+    match mode {
+        Mode::NonBlocking => 0,
+        Mode::Blocking => u32::MAX,
+        Mode::Timeout(ms) if first => u32::from(*ms),
+        Mode::Timeout(_) => 0,
+    }
+}
+
+impl<'clock, Spi, ChipSelectPin, ReadyPin, WakeupPin, ResetPin, Clock, const SOCKETS: usize, const BUFFER: usize> TcpStack for Adapter<'clock, Spi, ChipSelectPin, ReadyPin, WakeupPin, ResetPin, Clock, SOCKETS, BUFFER>
     where
         Spi: Transfer<u8>,
+        Spi::Error: Debug,
         ChipSelectPin: OutputPin,
+        ChipSelectPin::Error: Debug,
         ReadyPin: InputPin,
         WakeupPin: OutputPin,
         ResetPin: OutputPin,
         Clock: embedded_time::Clock + 'clock
 {
     type TcpSocket = TcpSocket;
-    type Error = TcpError;
+    type Error = Error<Spi::Error, ChipSelectPin::Error>;
 
     fn open(&self, mode: Mode) -> Result<Self::TcpSocket, Self::Error> {
         if let Some((index, socket)) = self
@@ -36,59 +97,360 @@ impl<'clock, Spi, ChipSelectPin, ReadyPin, WakeupPin, ResetPin, Clock> TcpStack
         {
             socket.state = State::Open;
             socket.mode = mode;
+            socket.remote = None;
             return Ok(TcpSocket(index));
         }
 
-        Err(TcpError::NoAvailableSockets)
+        Err(Error::NoAvailableSockets)
     }
 
     fn connect(&self, tcp_socket: Self::TcpSocket, remote: HostSocketAddr) -> Result<Self::TcpSocket, Self::Error> {
-        let socket = &self.sockets.borrow()[tcp_socket.0];
+        let socket = &mut self.sockets.borrow_mut()[tcp_socket.0];
         if !socket.is_open() {
-            return Err(TcpError::SocketNotOpen);
+            return Err(Error::SocketNotOpen);
         }
 
         let mut arbiter = self.arbiter.borrow_mut();
 
-        let response = arbiter.connect(
+        arbiter.connect(
             IpProtocol::Tcp,
             tcp_socket.0,
             remote,
-        );
+        )?;
 
-        if response.is_ok() {
-            return Ok(tcp_socket);
-        }
-
-        Err(TcpError::ConnectionRefused)
+        socket.state = State::Connected;
+        socket.generation = arbiter.generation();
+        Ok(tcp_socket)
     }
 
+    /// Checks the module for a pending close of a connected socket first.
     fn is_connected(&self, tcp_socket: &Self::TcpSocket) -> Result<bool, Self::Error> {
+        if self.sockets.borrow()[tcp_socket.0].is_connected() {
+            self.arbiter.borrow_mut().poll();
+        }
+        self.dispatch_events();
         let socket = &self.sockets.borrow()[tcp_socket.0];
         Ok(socket.is_connected())
     }
 
+    /// Writes as much of `buffer` as the module accepts, which may be less than all of it.
     fn write(&self, tcp_socket: &mut Self::TcpSocket, buffer: &[u8]) -> nb::Result<usize, Self::Error> {
-        let socket = &self.sockets.borrow()[tcp_socket.0];
+        self.dispatch_events();
+        let socket = &mut self.sockets.borrow_mut()[tcp_socket.0];
         if !socket.is_open() {
-            return Err(nb::Error::from(TcpError::SocketNotOpen));
+            return Err(nb::Error::from(Error::SocketNotOpen));
+        }
+        if buffer.is_empty() {
+            return Ok(0);
         }
 
         let mut arbiter = self.arbiter.borrow_mut();
 
-        let result = arbiter.write(tcp_socket.0, buffer);
+        let mut timer = None;
 
-        if result.is_ok() {
-            Ok(result.unwrap())
-        } else {
-            Err(nb::Error::from(TcpError::WriteError))
+        if let Mode::Timeout(ms) = socket.mode {
+            timer = Some(
+                self.clock.new_timer(Milliseconds(ms as u32)).start().unwrap()
+            );
+        }
+
+        let mut first = true;
+        loop {
+            let wait = socket_wait(&socket.mode, first);
+            first = false;
+            let len = arbiter.write(tcp_socket.0, buffer, wait).map_err(|e| {
+                // a refused write may mean the connection is gone, which the module must confirm
+                if matches!(e, Error::WriteFailed(_)) && arbiter.confirm_closed(tcp_socket.0) {
+                    socket.state = State::HalfClosed;
+                }
+                e
+            })?;
+
+            if len != 0 {
+                return Ok(len);
+            }
+
+            if socket.is_non_blocking() {
+                return Err(nb::Error::WouldBlock);
+            }
+
+            if let Some(ref timer) = timer {
+                if let Ok(true) = timer.is_expired() {
+                    return Err(nb::Error::Other(Error::Timeout));
+                }
+            }
         }
     }
 
+    /// Returns 0 once the peer has closed the connection and everything it
+    /// sent has been read.
     fn read(&self, tcp_socket: &mut Self::TcpSocket, buffer: &mut [u8]) -> nb::Result<usize, Self::Error> {
-        let mut socket = &mut self.sockets.borrow_mut()[tcp_socket.0];
+        let mut timer = None;
+
+        if let Mode::Timeout(ms) = self.sockets.borrow()[tcp_socket.0].mode {
+            timer = Some(
+                self.clock.new_timer(Milliseconds(ms as u32)).start().unwrap()
+            );
+        }
+
+        let mut first = true;
+        loop {
+            // a close noticed while reading takes effect on the next pass
+            self.dispatch_events();
+            let socket = &mut self.sockets.borrow_mut()[tcp_socket.0];
+            let half_closed = socket.is_half_closed();
+            if !socket.is_open() && !half_closed {
+                return Err(nb::Error::from(Error::SocketNotOpen));
+            }
+            if buffer.is_empty() {
+                return Ok(0);
+            }
+
+            let wait = socket_wait(&socket.mode, first);
+            first = false;
+            let mut arbiter = self.arbiter.borrow_mut();
+            let len = match arbiter.read(tcp_socket.0, buffer, wait) {
+                Ok(len) => len,
+                // the module may drop the socket as soon as it is drained
+                Err(_) if half_closed => 0,
+                Err(e) => {
+                    // a failed read may mean the connection is gone, which the module must confirm
+                    if matches!(e, Error::ReadFailed(_)) && arbiter.confirm_closed(tcp_socket.0) {
+                        socket.state = State::HalfClosed;
+                    }
+                    return Err(nb::Error::Other(e));
+                }
+            };
+
+            if len != 0 || half_closed {
+                return Ok(len);
+            }
+
+            if socket.is_non_blocking() {
+                return Err(nb::Error::WouldBlock);
+            }
+
+            if let Some(ref timer) = timer {
+                if let Ok(true) = timer.is_expired() {
+                    return Err(nb::Error::Other(Error::Timeout));
+                }
+            }
+        }
+    }
+
+    /// If the module fails to close the socket, it is reset, which
+    /// invalidates every other socket and queues an `Event::Reset`.
+    fn close(&self, tcp_socket: Self::TcpSocket) -> Result<(), Self::Error> {
+        self.close_socket(tcp_socket.0)
+    }
+}
+
+impl<'clock, Spi, ChipSelectPin, ReadyPin, WakeupPin, ResetPin, Clock, const SOCKETS: usize, const BUFFER: usize> Adapter<'clock, Spi, ChipSelectPin, ReadyPin, WakeupPin, ResetPin, Clock, SOCKETS, BUFFER>
+    where
+        Spi: Transfer<u8>,
+        Spi::Error: Debug,
+        ChipSelectPin: OutputPin,
+        ChipSelectPin::Error: Debug,
+        ReadyPin: InputPin,
+        WakeupPin: OutputPin,
+        ResetPin: OutputPin,
+        Clock: embedded_time::Clock + 'clock
+{
+    /// Connect an opened socket to `remote` over TLS, with the handshake
+    /// offloaded to the module. Once connected, the socket is used through
+    /// `TcpStack` like any other.
+    pub fn connect_tls(&self, tcp_socket: TcpSocket, remote: HostSocketAddr, tls: &TlsConfig) -> Result<TcpSocket, Error<Spi::Error, ChipSelectPin::Error>> {
+        let socket = &mut self.sockets.borrow_mut()[tcp_socket.0];
         if !socket.is_open() {
-            return Err(nb::Error::from(TcpError::SocketNotOpen));
+            return Err(Error::SocketNotOpen);
+        }
+
+        let mut arbiter = self.arbiter.borrow_mut();
+        arbiter.connect_tls(tcp_socket.0, remote, tls)?;
+
+        socket.state = State::Connected;
+        socket.generation = arbiter.generation();
+        Ok(tcp_socket)
+    }
+
+    /// Start a TCP server on `port`, using an opened but unconnected socket.
+    ///
+    /// The socket's mode determines whether `accept()` blocks.
+    pub fn listen(&self, tcp_socket: TcpSocket, port: u16, backlog: u8) -> Result<TcpListener, Error<Spi::Error, ChipSelectPin::Error>> {
+        let socket = &mut self.sockets.borrow_mut()[tcp_socket.0];
+        if !matches!(socket.state, State::Open) {
+            return Err(Error::SocketNotOpen);
+        }
+
+        let mut arbiter = self.arbiter.borrow_mut();
+        arbiter.listen(tcp_socket.0, port, backlog)?;
+
+        socket.state = State::Listening;
+        socket.generation = arbiter.generation();
+
+        Ok(TcpListener {
+            socket: Some(tcp_socket.0),
+            port,
+            backlog,
+            mode: copy_mode(&socket.mode),
+        })
+    }
+
+    /// Accept a client connection on a listening socket.
+    ///
+    /// The module delivers the client on the socket that was listening, so
+    /// the listener moves to another free socket to keep accepting. While
+    /// no socket is free, `accept()` returns `Error::NoAvailableSockets`.
+    pub fn accept(&self, listener: &mut TcpListener) -> nb::Result<(TcpSocket, SocketAddr), Error<Spi::Error, ChipSelectPin::Error>> {
+        let mut timer = None;
+
+        if let Mode::Timeout(ms) = listener.mode {
+            timer = Some(
+                self.clock.new_timer(Milliseconds(ms as u32)).start().unwrap()
+            );
+        }
+
+        loop {
+            if listener.socket.is_none() {
+                self.relisten(listener);
+            }
+
+            let index = listener.socket.ok_or(nb::Error::Other(Error::NoAvailableSockets))?;
+
+            self.arbiter.borrow_mut().poll();
+            self.dispatch_events();
+
+            let (accepted, listening) = {
+                let socket = &mut self.sockets.borrow_mut()[index];
+                (socket.remote.take(), socket.is_listening())
+            };
+
+            if accepted.is_none() && !listening {
+                // the module was reset from under the listener
+                listener.socket = None;
+                return Err(nb::Error::Other(Error::SocketNotOpen));
+            }
+
+            if let Some(remote) = accepted {
+                listener.socket = None;
+                self.relisten(listener);
+                return Ok((TcpSocket(index), remote));
+            }
+
+            if matches!(listener.mode, Mode::NonBlocking) {
+                return Err(nb::Error::WouldBlock);
+            }
+
+            if let Some(ref timer) = timer {
+                if let Ok(true) = timer.is_expired() {
+                    return Err(nb::Error::Other(Error::Timeout));
+                }
+            }
+        }
+    }
+
+    /// Stop a TCP server. Connections already accepted are unaffected.
+    ///
+    /// If the module fails to stop it, the module is reset, as for a socket
+    /// that fails to close.
+    pub fn close_listener(&self, listener: TcpListener) -> Result<(), Error<Spi::Error, ChipSelectPin::Error>> {
+        // a server lost to a reset is already gone from the module
+        self.dispatch_events();
+        if let Some(index) = listener.socket {
+            let result = if self.sockets.borrow()[index].is_listening() {
+                self.arbiter.borrow_mut().stop_listening(index)
+            } else {
+                Ok(())
+            };
+            self.sockets.borrow_mut()[index].state = State::Closed;
+            result?;
+        }
+        Ok(())
+    }
+
+    /// Close a TCP or UDP socket, freeing it once the module has, or has
+    /// been reset for failing to.
+    fn close_socket(&self, index: usize) -> Result<(), Error<Spi::Error, ChipSelectPin::Error>> {
+        // a socket lost to a reset is already gone from the module
+        self.dispatch_events();
+        let result = if self.sockets.borrow()[index].is_open() {
+            self.arbiter.borrow_mut().close(index)
+        } else {
+            Ok(())
+        };
+        self.sockets.borrow_mut()[index].state = State::Closed;
+        result
+    }
+
+    fn relisten(&self, listener: &mut TcpListener) {
+        let mut sockets = self.sockets.borrow_mut();
+        if let Some((index, socket)) = sockets
+            .iter_mut()
+            .enumerate()
+            .find(|(_, e)| e.is_closed())
+        {
+            let mut arbiter = self.arbiter.borrow_mut();
+            if arbiter.listen(index, listener.port, listener.backlog).is_ok() {
+                socket.state = State::Listening;
+                socket.generation = arbiter.generation();
+                socket.mode = copy_mode(&listener.mode);
+                listener.socket = Some(index);
+            }
+        }
+    }
+}
+
+impl<'clock, Spi, ChipSelectPin, ReadyPin, WakeupPin, ResetPin, Clock, const SOCKETS: usize, const BUFFER: usize> UdpStack for Adapter<'clock, Spi, ChipSelectPin, ReadyPin, WakeupPin, ResetPin, Clock, SOCKETS, BUFFER>
+    where
+        Spi: Transfer<u8>,
+        Spi::Error: Debug,
+        ChipSelectPin: OutputPin,
+        ChipSelectPin::Error: Debug,
+        ReadyPin: InputPin,
+        WakeupPin: OutputPin,
+        ResetPin: OutputPin,
+        Clock: embedded_time::Clock + 'clock
+{
+    type UdpSocket = UdpSocket;
+    type Error = Error<Spi::Error, ChipSelectPin::Error>;
+
+    fn open(&self, remote: SocketAddr, mode: Mode) -> Result<Self::UdpSocket, Self::Error> {
+        let mut sockets = self.sockets.borrow_mut();
+        let (index, socket) = sockets
+            .iter_mut()
+            .enumerate()
+            .find(|(_, e)| e.is_closed())
+            .ok_or(Error::NoAvailableSockets)?;
+
+        let mut arbiter = self.arbiter.borrow_mut();
+
+        arbiter.connect(
+            IpProtocol::Udp,
+            index,
+            HostSocketAddr::new(HostAddr::from(remote.ip()), remote.port()),
+        )?;
+
+        socket.state = State::Connected;
+        socket.mode = mode;
+        socket.generation = arbiter.generation();
+        Ok(UdpSocket(index))
+    }
+
+    fn write(&self, udp_socket: &mut Self::UdpSocket, buffer: &[u8]) -> nb::Result<(), Self::Error> {
+        self.dispatch_events();
+        let socket = &self.sockets.borrow()[udp_socket.0];
+        if !socket.is_open() {
+            return Err(nb::Error::from(Error::SocketNotOpen));
+        }
+
+        // the module can't send an empty datagram
+        if buffer.is_empty() {
+            return Err(nb::Error::from(Error::EmptyDatagram));
+        }
+
+        // a datagram must go out in a single write
+        if buffer.len() > MAX_WRITE_LEN {
+            return Err(nb::Error::from(Error::DatagramTooLarge));
         }
 
         let mut arbiter = self.arbiter.borrow_mut();
@@ -101,12 +463,52 @@ impl<'clock, Spi, ChipSelectPin, ReadyPin, WakeupPin, ResetPin, Clock> TcpStack
             );
         }
 
+        let mut first = true;
         loop {
-            let len = arbiter.read(tcp_socket.0, buffer).map_err(|_|
-                {
-                    socket.state = State::HalfClosed;
-                    TcpError::ReadError
-                })?;
+            let wait = socket_wait(&socket.mode, first);
+            first = false;
+            match arbiter.write_datagram(udp_socket.0, buffer, wait)? {
+                len if len == buffer.len() => return Ok(()),
+                // nothing was sent, so it may be tried again
+                0 => {}
+                // part of a datagram is as good as none of it
+                _ => return Err(nb::Error::from(Error::WriteFailed(Default::default()))),
+            }
+
+            if socket.is_non_blocking() {
+                return Err(nb::Error::WouldBlock);
+            }
+
+            if let Some(ref timer) = timer {
+                if let Ok(true) = timer.is_expired() {
+                    return Err(nb::Error::Other(Error::Timeout));
+                }
+            }
+        }
+    }
+
+    fn read(&self, udp_socket: &mut Self::UdpSocket, buffer: &mut [u8]) -> nb::Result<usize, Self::Error> {
+        self.dispatch_events();
+        let socket = &self.sockets.borrow()[udp_socket.0];
+        if !socket.is_open() {
+            return Err(nb::Error::from(Error::SocketNotOpen));
+        }
+
+        let mut arbiter = self.arbiter.borrow_mut();
+
+        let mut timer = None;
+
+        if let Mode::Timeout(ms) = socket.mode {
+            timer = Some(
+                self.clock.new_timer(Milliseconds(ms as u32)).start().unwrap()
+            );
+        }
+
+        let mut first = true;
+        loop {
+            let wait = socket_wait(&socket.mode, first);
+            first = false;
+            let len = arbiter.read(udp_socket.0, buffer, wait)?;
 
             if len != 0 {
                 return Ok(len);
@@ -118,22 +520,54 @@ impl<'clock, Spi, ChipSelectPin, ReadyPin, WakeupPin, ResetPin, Clock> TcpStack
 
             if let Some(ref timer) = timer {
                 if let Ok(true) = timer.is_expired() {
-                    return Ok(0)
+                    return Err(nb::Error::Other(Error::Timeout));
                 }
             }
         }
     }
 
-    fn close(&self, tcp_socket: Self::TcpSocket) -> Result<(), Self::Error> {
-        let mut socket = &mut self.sockets.borrow_mut()[tcp_socket.0];
-        if socket.is_open() {
-            socket.state = State::Closed;
-            let mut arbiter = self.arbiter.borrow_mut();
-            arbiter.close(tcp_socket.0).map_err(|_| TcpError::Impl(TcpImplError::Unknown))?;
-        } else {
-            socket.state = State::Closed;
+    /// If the module fails to close the socket, it is reset, which
+    /// invalidates every other socket and queues an `Event::Reset`.
+    fn close(&self, udp_socket: Self::UdpSocket) -> Result<(), Self::Error> {
+        self.close_socket(udp_socket.0)
+    }
+}
+
+impl<'clock, Spi, ChipSelectPin, ReadyPin, WakeupPin, ResetPin, Clock, const SOCKETS: usize, const BUFFER: usize> Dns for Adapter<'clock, Spi, ChipSelectPin, ReadyPin, WakeupPin, ResetPin, Clock, SOCKETS, BUFFER>
+    where
+        Spi: Transfer<u8>,
+        Spi::Error: Debug,
+        ChipSelectPin: OutputPin,
+        ChipSelectPin::Error: Debug,
+        ReadyPin: InputPin,
+        WakeupPin: OutputPin,
+        ResetPin: OutputPin,
+        Clock: embedded_time::Clock + 'clock
+{
+    type Error = Error<Spi::Error, ChipSelectPin::Error>;
+
+    fn gethostbyname(&self, hostname: &str, addr_type: AddrType) -> Result<HostAddr, Self::Error> {
+        // the module only resolves A records
+        if addr_type == AddrType::IPv6 {
+            return Err(Error::UnsupportedAddressType);
         }
 
-        Ok(())
+        // a hostname is at most 253 characters, and may not contain our command terminator
+        if hostname.is_empty() || hostname.len() > 253 || hostname.contains('\r') {
+            return Err(Error::InvalidHostname);
+        }
+
+        let mut arbiter = self.arbiter.borrow_mut();
+        let ip = arbiter.resolve(hostname)?;
+
+        let mut name = String::<U256>::new();
+        name.push_str(hostname).map_err(|_| Error::InvalidHostname)?;
+
+        Ok(HostAddr::new(ip, Some(name)))
+    }
+
+    fn gethostbyaddr(&self, _addr: IpAddr) -> Result<String<U256>, Self::Error> {
+        // the module has no reverse lookup
+        Err(Error::NoSuchHost(Default::default()))
     }
 }
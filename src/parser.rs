@@ -2,19 +2,23 @@ use nom::{
     do_parse,
     complete,
     named,
-    named_args,
     alt,
     tag,
     char,
     take,
     take_until,
+    map_res,
+    opt,
+    eof,
 };
-use drogue_nom_utils::{
-    parse_usize,
-};
+use nom::IResult;
+use nom::error::ErrorKind;
+use nom::character::complete::digit1;
+use core::convert::TryFrom;
 
 use heapless::String;
-use crate::adapter::JoinError;
+use crate::adapter::{JoinResult, ModuleError, ScanResult, SecurityType, Station};
+use drogue_network::addr::{IpAddr, SocketAddr};
 
 named!(
     pub ok,
@@ -31,27 +35,93 @@ named!(
     tag!("> ")
 );
 
+named!(
+    pub crlf,
+    tag!("\r\n")
+);
+
+/// Collect what the module printed with a failure, skipping the `ERROR`,
+/// `OK` and prompt framing, for `Error` variants carrying a `ModuleError`.
+pub(crate) fn module_error(response: &[u8]) -> ModuleError {
+    let mut error = ModuleError::default();
+
+    for line in response.split(|b| *b == b'\n') {
+        let mut line = trim(line);
+        if line.starts_with(b"ERROR") {
+            line = trim(&line[5..]);
+            if line.starts_with(b":") {
+                line = trim(&line[1..]);
+            }
+        }
+        if line.is_empty() || line == b"OK" || line == b">" {
+            continue;
+        }
+
+        if !error.message.is_empty() && error.message.push_str("; ").is_err() {
+            break;
+        }
+        for b in line {
+            let c = if b.is_ascii_graphic() || *b == b' ' { *b as char } else { '?' };
+            if error.message.push(c).is_err() {
+                return error;
+            }
+        }
+    }
+
+    error
+}
+
+fn trim(mut input: &[u8]) -> &[u8] {
+    while let [first, rest @ ..] = input {
+        if first.is_ascii_whitespace() {
+            input = rest;
+        } else {
+            break;
+        }
+    }
+    while let [rest @ .., last] = input {
+        if last.is_ascii_whitespace() {
+            input = rest;
+        } else {
+            break;
+        }
+    }
+    input
+}
+
 #[derive(Debug)]
 pub(crate) enum JoinResponse {
-    Ok,
+    Ok(JoinResult),
     JoinError,
 }
 
+fn parse_ip_addr(input: &[u8]) -> Result<IpAddr, ()> {
+    core::str::from_utf8(input)
+        .map_err(|_| ())?
+        .parse()
+        .map_err(|_| ())
+}
+
 // [JOIN   ] drogue,192.168.1.174,0,0
 #[rustfmt::skip]
 named!(
     pub(crate) join<JoinResponse>,
     do_parse!(
         tag!("[JOIN   ] ") >>
-        ssid: take_until!(",") >>
+        ssid: map_res!(take_until!(","), parse_ssid) >>
         char!(',') >>
-        ip: take_until!(",") >>
+        ip: map_res!(take_until!(","), parse_ip_addr) >>
         char!(',') >>
         tag!("0,0") >>
         tag!("\r\n") >>
         ok >>
         (
-            JoinResponse::Ok
+            JoinResponse::Ok(
+                JoinResult {
+                    ssid,
+                    ip,
+                }
+            )
         )
     )
 );
@@ -86,19 +156,30 @@ named!(
     )
 );
 
-pub(crate) enum ConnectResponse {
+pub(crate) enum ConnectResponse<'a> {
     Ok,
-    Error,
+    /// The module's messages leading up to the failure.
+    Error(&'a [u8]),
 }
 
 
+// [TCP  RC] Connecting to 192.168.1.245
+// UDP sockets may connect without a message
 named!(
     pub(crate) connected<ConnectResponse>,
     do_parse!(
         tag!("\r\n") >>
-        tag!("[TCP  RC] Connecting to ") >>
-        take_until!( "\r\n") >>
-        tag!("\r\n") >>
+        opt!(
+            complete!(
+                do_parse!(
+                    tag!("[") >>
+                    take_until!("] Connecting to ") >>
+                    take_until!( "\r\n") >>
+                    tag!("\r\n") >>
+                    ()
+                )
+            )
+        ) >>
         ok >>
         prompt >>
         (
@@ -110,10 +191,10 @@ named!(
 named!(
     pub(crate) connection_failure<ConnectResponse>,
     do_parse!(
-        take_until!( "ERROR" ) >>
+        reason: take_until!( "ERROR" ) >>
         error >>
         (
-            ConnectResponse::Error
+            ConnectResponse::Error(reason)
         )
     )
 );
@@ -126,6 +207,11 @@ named!(
     )
 );
 
+/// Whether the module's messages for a failed connect point at the TLS layer.
+pub(crate) fn is_tls_failure(reason: &[u8]) -> bool {
+    [&b"SSL"[..], b"TLS", b"HANDSHAKE", b"CERT"].iter().any(|needle| contains(reason, needle))
+}
+
 #[derive(Debug)]
 pub(crate) enum CloseResponse {
     Ok,
@@ -166,6 +252,210 @@ named!(
     )
 );
 
+#[derive(Debug)]
+pub(crate) enum ResolveResponse<'a> {
+    Ok(IpAddr),
+    Error(&'a [u8]),
+}
+
+#[rustfmt::skip]
+named!(
+    pub(crate) resolved<ResolveResponse>,
+    do_parse!(
+        crlf >>
+        ip: map_res!(take_until!("\r\n"), parse_ip_addr) >>
+        crlf >>
+        ok >>
+        prompt >>
+        (
+            ResolveResponse::Ok(ip)
+        )
+    )
+);
+
+// the module may report a reason before or after ERROR
+#[rustfmt::skip]
+named!(
+    pub(crate) resolve_error<ResolveResponse>,
+    do_parse!(
+        crlf >>
+        before: take_until!("ERROR") >>
+        error >>
+        after: take_until!("> ") >>
+        prompt >>
+        (
+            ResolveResponse::Error(if before.is_empty() { after } else { before })
+        )
+    )
+);
+
+named!(
+    pub(crate) resolve_response<ResolveResponse>,
+    alt!(
+          complete!(resolved)
+        | complete!(resolve_error)
+    )
+);
+
+/// Outcome of a command which only reports success or failure,
+/// possibly preceded by informational messages.
+#[derive(Debug)]
+pub(crate) enum CommandResponse {
+    Ok,
+    Error,
+}
+
+// [WIFI   ] Disconnected
+// [TCP SVR] Listening on: 192.168.1.174:8080
+named!(
+    pub(crate) command_response<CommandResponse>,
+    alt!(
+          complete!(do_parse!( crlf >> take_until!("ERROR") >> error >> prompt >> ( CommandResponse::Error ) ))
+        | complete!(do_parse!( crlf >> take_until!("OK\r\n") >> ok >> prompt >> ( CommandResponse::Ok ) ))
+    )
+);
+
+/// Decimal digits, failing rather than wrapping or panicking when the value does not fit.
+fn parse_decimal<T: TryFrom<usize>>(input: &[u8]) -> Result<T, ()> {
+    let value = input.iter().try_fold(0usize, |value, digit| {
+        if !digit.is_ascii_digit() {
+            return Err(());
+        }
+        value.checked_mul(10)
+            .and_then(|value| value.checked_add((digit - b'0') as usize))
+            .ok_or(())
+    })?;
+    T::try_from(value).map_err(|_| ())
+}
+
+named!(
+    parse_u8<u8>,
+    map_res!(digit1, parse_decimal)
+);
+
+named!(
+    parse_u16<u16>,
+    map_res!(digit1, parse_decimal)
+);
+
+named!(
+    parse_usize<usize>,
+    map_res!(digit1, parse_decimal)
+);
+
+// Accepted 192.168.1.2:52344
+#[rustfmt::skip]
+named!(
+    pub(crate) accepted<SocketAddr>,
+    do_parse!(
+        tag!("Accepted ") >>
+        ip: map_res!(take_until!(":"), parse_ip_addr) >>
+        char!(':') >>
+        port: parse_u16 >>
+        (
+            SocketAddr::new(ip, port)
+        )
+    )
+);
+
+/// An unsolicited message from the module.
+#[derive(Debug)]
+pub(crate) enum Message<'a> {
+    /// A client connected to a server socket, which the message doesn't name.
+    Accepted(SocketAddr),
+    /// The peer closed a connection, on a socket the message doesn't name.
+    PeerClosed,
+    /// The connection to the access point was lost.
+    Disconnected,
+    Other(&'a [u8]),
+}
+
+#[derive(Debug)]
+pub(crate) enum MessageResponse<'a> {
+    Message(Message<'a>),
+    Empty,
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack.windows(needle.len()).any(|w| w.eq_ignore_ascii_case(needle))
+}
+
+fn classify_message(body: &[u8]) -> MessageResponse<'_> {
+    if body.is_empty() {
+        return MessageResponse::Empty;
+    }
+
+    let message = if let Ok((_, remote)) = accepted(body) {
+        Message::Accepted(remote)
+    } else if contains(body, b"disconnected") {
+        Message::Disconnected
+    } else if contains(body, b"closed") {
+        Message::PeerClosed
+    } else {
+        Message::Other(body)
+    };
+
+    MessageResponse::Message(message)
+}
+
+// [SOMA]Accepted 192.168.1.2:52344[EOMA]
+// [SOMA][TCP  RC] Connection closed[EOMA]
+// [SOMA][WIFI   ] Disconnected[EOMA]
+// [SOMA][EOMA]
+#[rustfmt::skip]
+named!(
+    pub(crate) message_response<MessageResponse>,
+    do_parse!(
+        crlf >>
+        tag!("[SOMA]") >>
+        body: take_until!("[EOMA]") >>
+        tag!("[EOMA]") >>
+        crlf >>
+        ok >>
+        prompt >>
+        (
+            classify_message(body)
+        )
+    )
+);
+
+/// What `P?` reports about the selected socket.
+#[derive(Debug)]
+pub(crate) struct SocketStatus {
+    pub(crate) remote: SocketAddr,
+    pub(crate) connected: bool,
+}
+
+// socket, protocol, local address and port, remote address and port, then
+// whether it is serving, its backlog and whether it holds a connection
+// 0,0,192.168.1.174,49152,192.168.1.245,8080,0,0,1
+#[rustfmt::skip]
+named!(
+    pub(crate) socket_status<SocketStatus>,
+    do_parse!(
+        crlf >>
+        parse_u8 >> char!(',') >>
+        parse_u8 >> char!(',') >>
+        take_until!(",") >> char!(',') >>
+        parse_u16 >> char!(',') >>
+        ip: map_res!(take_until!(","), parse_ip_addr) >> char!(',') >>
+        port: parse_u16 >> char!(',') >>
+        parse_u8 >> char!(',') >>
+        parse_u8 >> char!(',') >>
+        connected: parse_u8 >>
+        take_until!("\r\n") >>
+        crlf >>
+        ok >>
+        prompt >>
+        (
+            SocketStatus {
+                remote: SocketAddr::new(ip, port),
+                connected: connected != 0,
+            }
+        )
+    )
+);
+
 #[derive(Debug)]
 pub(crate) enum WriteResponse {
     Ok(usize),
@@ -215,16 +505,35 @@ pub(crate) enum ReadResponse<'a> {
     Err,
 }
 
+pub(crate) const READ_TRAILER: &[u8] = b"\r\nOK\r\n> ";
+
+/// Bytes framing the payload of a successful `R0` response.
+pub(crate) const READ_FRAMING: usize = 2 + READ_TRAILER.len();
+
+/// `input` must be the whole `R0` response, as its length is what delimits
+/// the payload: everything between the leading `\r\n` and the trailer is
+/// data, whatever bytes it contains.
+pub(crate) fn read_data(input: &[u8]) -> IResult<&[u8], ReadResponse<'_>> {
+    let (input, _) = crlf(input)?;
+    if input.ends_with(READ_TRAILER) {
+        let data = &input[..input.len() - READ_TRAILER.len()];
+        Ok((&input[input.len()..], ReadResponse::Ok(data)))
+    } else {
+        Err(nom::Err::Error((input, ErrorKind::TakeUntil)))
+    }
+}
+
 named!(
-    pub(crate) read_data<ReadResponse>,
+    pub(crate) read_failure<ReadResponse>,
     do_parse!(
         tag!("\r\n") >>
-        data: take_until!("\r\nOK\r\n>") >>
+        tag!("-1") >>
         tag!("\r\n") >>
         ok >>
         prompt >>
+        eof!() >>
         (
-            ReadResponse::Ok(data)
+            ReadResponse::Err
         )
     )
 );
@@ -232,10 +541,9 @@ named!(
 named!(
     pub(crate) read_error<ReadResponse>,
     do_parse!(
-        tag!("\r\n") >>
-        tag!("-1") >>
-        tag!("\r\n") >>
-        ok >>
+        crlf >>
+        take_until!("ERROR") >>
+        error >>
         prompt >>
         (
             ReadResponse::Err
@@ -243,11 +551,704 @@ named!(
     )
 );
 
+// a bare -1 is how the module reports a failed read, so it is never taken as data
 named!(
     pub(crate) read_response<ReadResponse>,
     alt!(
-          complete!(read_data)
+          complete!(read_failure)
+        | complete!(read_data)
         | complete!(read_error)
     )
 );
 
+
+fn hex_digit(c: u8) -> Option<u8> {
+    match c {
+        b'0'..=b'9' => Some(c - b'0'),
+        b'a'..=b'f' => Some(c - b'a' + 10),
+        b'A'..=b'F' => Some(c - b'A' + 10),
+        _ => None,
+    }
+}
+
+fn parse_hex_byte(input: &[u8]) -> Result<u8, ()> {
+    if input.len() != 2 {
+        return Err(());
+    }
+    let hi = hex_digit(input[0]).ok_or(())?;
+    let lo = hex_digit(input[1]).ok_or(())?;
+    Ok((hi << 4) | lo)
+}
+
+named!(
+    pub(crate) hex_byte<u8>,
+    map_res!(take!(2), parse_hex_byte)
+);
+
+// C4:7F:51:01:02:03
+#[rustfmt::skip]
+named!(
+    pub(crate) mac_addr<[u8; 6]>,
+    do_parse!(
+        b0: hex_byte >> char!(':') >>
+        b1: hex_byte >> char!(':') >>
+        b2: hex_byte >> char!(':') >>
+        b3: hex_byte >> char!(':') >>
+        b4: hex_byte >> char!(':') >>
+        b5: hex_byte >>
+        (
+            [b0, b1, b2, b3, b4, b5]
+        )
+    )
+);
+
+fn signed(sign: Option<char>, value: usize) -> i16 {
+    let value = value.min(i16::MAX as usize) as i16;
+    if sign.is_some() {
+        -value
+    } else {
+        value
+    }
+}
+
+named!(
+    pub(crate) rssi<i16>,
+    do_parse!(
+        sign: opt!(char!('-')) >>
+        value: parse_usize >>
+        (
+            signed(sign, value)
+        )
+    )
+);
+
+fn parse_ssid(input: &[u8]) -> Result<String<heapless::consts::U32>, ()> {
+    let ssid = core::str::from_utf8(input).map_err(|_| ())?;
+    let mut result = String::new();
+    result.push_str(ssid)?;
+    Ok(result)
+}
+
+fn parse_security(input: &[u8]) -> SecurityType {
+    match input {
+        b"Open" => SecurityType::Open,
+        b"WEP" => SecurityType::Wep,
+        b"WPA AES" | b"WPA" => SecurityType::Wpa,
+        b"WPA2 AES" | b"WPA2" => SecurityType::Wpa2,
+        b"WPA WPA2" => SecurityType::WpaMixed,
+        b"WPA2 TKIP" => SecurityType::Wpa2Tkip,
+        _ => SecurityType::Unknown,
+    }
+}
+
+// #001,"drogue",C4:7F:51:01:02:03,-52,72.0,Infrastructure,WPA2 AES,2.4GHz,6,,
+#[rustfmt::skip]
+named!(
+    pub(crate) scan_result<ScanResult>,
+    do_parse!(
+        char!('#') >>
+        parse_usize >>
+        char!(',') >>
+        char!('"') >>
+        ssid: map_res!(take_until!("\","), parse_ssid) >>
+        char!('"') >>
+        char!(',') >>
+        bssid: mac_addr >>
+        char!(',') >>
+        rssi: rssi >>
+        char!(',') >>
+        take_until!(",") >>
+        char!(',') >>
+        take_until!(",") >>
+        char!(',') >>
+        security: take_until!(",") >>
+        char!(',') >>
+        take_until!(",") >>
+        char!(',') >>
+        channel: parse_u8 >>
+        take_until!("\r\n") >>
+        crlf >>
+        (
+            ScanResult {
+                ssid,
+                bssid,
+                rssi,
+                channel,
+                security: parse_security(security),
+            }
+        )
+    )
+);
+
+#[derive(Debug)]
+pub(crate) struct NetworkSettings {
+    pub(crate) ssid: String<heapless::consts::U32>,
+    pub(crate) ip: IpAddr,
+    pub(crate) netmask: IpAddr,
+    pub(crate) gateway: IpAddr,
+    pub(crate) primary_dns: IpAddr,
+    pub(crate) secondary_dns: IpAddr,
+}
+
+// ssid,password,security,dhcp,ip-version,ip,netmask,gateway,dns1,dns2,retries,auto-connect,...
+#[rustfmt::skip]
+named!(
+    pub(crate) network_settings<NetworkSettings>,
+    do_parse!(
+        crlf >>
+        ssid: map_res!(take_until!(","), parse_ssid) >>
+        char!(',') >>
+        take_until!(",") >>
+        char!(',') >>
+        parse_u8 >>
+        char!(',') >>
+        parse_u8 >>
+        char!(',') >>
+        parse_u8 >>
+        char!(',') >>
+        ip: map_res!(take_until!(","), parse_ip_addr) >>
+        char!(',') >>
+        netmask: map_res!(take_until!(","), parse_ip_addr) >>
+        char!(',') >>
+        gateway: map_res!(take_until!(","), parse_ip_addr) >>
+        char!(',') >>
+        primary_dns: map_res!(take_until!(","), parse_ip_addr) >>
+        char!(',') >>
+        secondary_dns: map_res!(take_until!(","), parse_ip_addr) >>
+        take_until!("\r\n") >>
+        crlf >>
+        ok >>
+        prompt >>
+        (
+            NetworkSettings {
+                ssid,
+                ip,
+                netmask,
+                gateway,
+                primary_dns,
+                secondary_dns,
+            }
+        )
+    )
+);
+
+named!(
+    pub(crate) rssi_response<i16>,
+    do_parse!(
+        crlf >>
+        rssi: rssi >>
+        crlf >>
+        ok >>
+        prompt >>
+        (
+            rssi
+        )
+    )
+);
+
+named!(
+    pub(crate) mac_response<[u8; 6]>,
+    do_parse!(
+        crlf >>
+        mac: mac_addr >>
+        crlf >>
+        ok >>
+        prompt >>
+        (
+            mac
+        )
+    )
+);
+
+// #001,C4:7F:51:01:02:03,192.168.10.2
+#[rustfmt::skip]
+named!(
+    pub(crate) station<Station>,
+    do_parse!(
+        opt!(
+            complete!(
+                do_parse!( char!('#') >> parse_usize >> char!(',') >> () )
+            )
+        ) >>
+        mac: mac_addr >>
+        char!(',') >>
+        ip: map_res!(take_until!("\r\n"), parse_ip_addr) >>
+        crlf >>
+        (
+            Station {
+                mac,
+                ip,
+            }
+        )
+    )
+);
+
+#[derive(Debug)]
+pub(crate) enum ListResponse {
+    Ok,
+    Error,
+}
+
+named!(
+    pub(crate) list_end<ListResponse>,
+    alt!(
+          do_parse!( ok >> prompt >> ( ListResponse::Ok ) )
+        | do_parse!( take_until!("ERROR") >> error >> ( ListResponse::Error ) )
+    )
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use drogue_network::addr::Ipv4Addr;
+
+    #[test]
+    fn parse_join_ok() {
+        let response = b"\r\n[JOIN   ] drogue,192.168.1.174,0,0\r\nOK\r\n> ";
+        let (_, result) = join_response(response).unwrap();
+        if let JoinResponse::Ok(result) = result {
+            assert_eq!(result.ssid.as_str(), "drogue");
+            assert_eq!(result.ip, IpAddr::V4(Ipv4Addr::new(192, 168, 1, 174)));
+        } else {
+            panic!("expected JoinResponse::Ok, got {:?}", result);
+        }
+    }
+
+    #[test]
+    fn parse_join_failure() {
+        let response = b"\r\n[JOIN   ] drogue\r\n[JOIN   ] Failed\r\nERROR\r\n> ";
+        assert!(matches!(join_response(response), Ok((_, JoinResponse::JoinError))));
+    }
+
+    const SCAN: &[u8] = b"\r\n\
+#001,\"drogue\",C4:7F:51:01:02:03,-52,72.0,Infrastructure,WPA2 AES,2.4GHz,6,,\r\n\
+#002,\"guest, 2nd floor\",00:1a:2B:3c:4D:5e,-81,54.0,Infrastructure,Open,2.4GHz,11,,\r\n\
+#003,\"\",F8:D1:11:AA:BB:CC,-90,144.4,Infrastructure,WPA WPA2,2.4GHz,1,,\r\n\
+OK\r\n> ";
+
+    #[test]
+    fn parse_scan_results() {
+        let (input, _) = crlf(SCAN).unwrap();
+
+        let (input, first) = scan_result(input).unwrap();
+        assert_eq!(first.ssid.as_str(), "drogue");
+        assert_eq!(first.bssid, [0xC4, 0x7F, 0x51, 0x01, 0x02, 0x03]);
+        assert_eq!(first.rssi, -52);
+        assert_eq!(first.channel, 6);
+        assert_eq!(first.security, SecurityType::Wpa2);
+
+        let (input, second) = scan_result(input).unwrap();
+        assert_eq!(second.ssid.as_str(), "guest, 2nd floor");
+        assert_eq!(second.bssid, [0x00, 0x1A, 0x2B, 0x3C, 0x4D, 0x5E]);
+        assert_eq!(second.rssi, -81);
+        assert_eq!(second.channel, 11);
+        assert_eq!(second.security, SecurityType::Open);
+
+        let (input, third) = scan_result(input).unwrap();
+        assert_eq!(third.ssid.as_str(), "");
+        assert_eq!(third.channel, 1);
+        assert_eq!(third.security, SecurityType::WpaMixed);
+
+        assert!(scan_result(input).is_err());
+        assert!(matches!(list_end(input), Ok((b"", ListResponse::Ok))));
+    }
+
+    #[test]
+    fn parse_empty_scan() {
+        let (input, _) = crlf(b"\r\nOK\r\n> ").unwrap();
+        assert!(scan_result(input).is_err());
+        assert!(matches!(list_end(input), Ok((b"", ListResponse::Ok))));
+    }
+
+    #[test]
+    fn parse_scan_error() {
+        let (input, _) = crlf(b"\r\nERROR\r\n> ").unwrap();
+        assert!(matches!(list_end(input), Ok((_, ListResponse::Error))));
+    }
+
+    #[test]
+    fn parse_resolve() {
+        let response = b"\r\n93.184.216.34\r\nOK\r\n> ";
+        if let Ok((_, ResolveResponse::Ok(ip))) = resolve_response(response) {
+            assert_eq!(ip, IpAddr::V4(Ipv4Addr::new(93, 184, 216, 34)));
+        } else {
+            panic!("expected a resolved address");
+        }
+
+        let response = b"\r\nERROR\r\nDNS Lookup Timeout\r\n> ";
+        assert!(matches!(
+            resolve_response(response),
+            Ok((_, ResolveResponse::Error(b"DNS Lookup Timeout\r\n")))
+        ));
+
+        let response = b"\r\n-1\r\nERROR\r\n> ";
+        assert!(matches!(
+            resolve_response(response),
+            Ok((_, ResolveResponse::Error(b"-1\r\n")))
+        ));
+    }
+
+    #[test]
+    fn parse_module_error() {
+        let error = module_error(b"\r\n[JOIN   ] drogue\r\n[JOIN   ] Failed\r\nERROR\r\n> ");
+        assert_eq!(error.message(), "[JOIN   ] drogue; [JOIN   ] Failed");
+        assert_eq!(error.code(), None);
+
+        let error = module_error(b"\r\n-1\r\nOK\r\n> ");
+        assert_eq!(error.message(), "-1");
+        assert_eq!(error.code(), Some(-1));
+
+        let error = module_error(b"\r\nERROR:Invalid parameter\r\n> ");
+        assert_eq!(error.message(), "Invalid parameter");
+
+        assert_eq!(module_error(b"\r\nERROR\r\n> ").message(), "");
+        assert_eq!(module_error(b"\r\n\x01\xff\r\nERROR\r\n> ").message(), "??");
+
+        let error = module_error(&[b'x'; 100]);
+        assert_eq!(error.message().len(), 64);
+    }
+
+    #[test]
+    fn parse_connect() {
+        assert!(matches!(
+            connect_response(b"\r\n[TCP  RC] Connecting to 192.168.1.245\r\nOK\r\n> "),
+            Ok((_, ConnectResponse::Ok))
+        ));
+        assert!(matches!(
+            connect_response(b"\r\nOK\r\n> "),
+            Ok((_, ConnectResponse::Ok))
+        ));
+        assert!(matches!(
+            connect_response(b"\r\n[TCP  RC] Connecting to 192.168.1.245\r\nERROR\r\n> "),
+            Ok((_, ConnectResponse::Error(b"\r\n[TCP  RC] Connecting to 192.168.1.245\r\n")))
+        ));
+    }
+
+    #[test]
+    fn parse_command() {
+        assert!(matches!(command_response(b"\r\nOK\r\n> "), Ok((_, CommandResponse::Ok))));
+        assert!(matches!(command_response(b"\r\n[WIFI   ] Disconnected\r\nOK\r\n> "), Ok((_, CommandResponse::Ok))));
+        assert!(matches!(command_response(b"\r\nERROR\r\n> "), Ok((_, CommandResponse::Error))));
+    }
+
+    #[test]
+    fn parse_messages() {
+        let response = b"\r\n[SOMA]Accepted 192.168.1.2:52344[EOMA]\r\nOK\r\n> ";
+        if let Ok((_, MessageResponse::Message(Message::Accepted(remote)))) = message_response(response) {
+            assert_eq!(remote, SocketAddr::new(IpAddr::V4(Ipv4Addr::new(192, 168, 1, 2)), 52344));
+        } else {
+            panic!("expected an accepted connection");
+        }
+
+        let response = b"\r\n[SOMA][EOMA]\r\nOK\r\n> ";
+        assert!(matches!(message_response(response), Ok((_, MessageResponse::Empty))));
+
+        let response = b"\r\n[SOMA][TCP  RC] Connection closed[EOMA]\r\nOK\r\n> ";
+        assert!(matches!(message_response(response), Ok((_, MessageResponse::Message(Message::PeerClosed)))));
+
+        let response = b"\r\n[SOMA][WIFI   ] Disconnected[EOMA]\r\nOK\r\n> ";
+        assert!(matches!(message_response(response), Ok((_, MessageResponse::Message(Message::Disconnected)))));
+
+        let response = b"\r\n[SOMA]Accepted 192.168.1.2:99999[EOMA]\r\nOK\r\n> ";
+        assert!(matches!(message_response(response), Ok((_, MessageResponse::Message(Message::Other(_))))));
+    }
+
+    #[test]
+    fn parse_socket_status() {
+        let response = b"\r\n0,0,192.168.1.174,49152,192.168.1.245,8080,0,0,1\r\nOK\r\n> ";
+        let (_, status) = socket_status(response).unwrap();
+        assert_eq!(status.remote, SocketAddr::new(IpAddr::V4(Ipv4Addr::new(192, 168, 1, 245)), 8080));
+        assert!(status.connected);
+
+        // trailing fields of newer firmware are ignored
+        let response = b"\r\n1,0,192.168.1.174,8080,0.0.0.0,0,1,1,0,0\r\nOK\r\n> ";
+        assert!(!socket_status(response).unwrap().1.connected);
+
+        assert!(socket_status(b"\r\nERROR\r\n> ").is_err());
+        assert!(socket_status(b"\r\n0,0,192.168.1.174,49152\r\nOK\r\n> ").is_err());
+    }
+
+    #[test]
+    fn parse_network_settings() {
+        let response = b"\r\ndrogue,rodneygnome,3,1,0,192.168.1.174,255.255.255.0,192.168.1.1,192.168.1.1,0.0.0.0,5,0,0,US,1\r\nOK\r\n> ";
+        let (_, settings) = network_settings(response).unwrap();
+        assert_eq!(settings.ssid.as_str(), "drogue");
+        assert_eq!(settings.ip, IpAddr::V4(Ipv4Addr::new(192, 168, 1, 174)));
+        assert_eq!(settings.netmask, IpAddr::V4(Ipv4Addr::new(255, 255, 255, 0)));
+        assert_eq!(settings.gateway, IpAddr::V4(Ipv4Addr::new(192, 168, 1, 1)));
+        assert_eq!(settings.primary_dns, IpAddr::V4(Ipv4Addr::new(192, 168, 1, 1)));
+        assert_eq!(settings.secondary_dns, IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)));
+    }
+
+    #[test]
+    fn parse_rssi_and_mac() {
+        assert_eq!(rssi_response(b"\r\n-52\r\nOK\r\n> ").unwrap().1, -52);
+        assert_eq!(
+            mac_response(b"\r\nC4:7F:51:8E:12:0A\r\nOK\r\n> ").unwrap().1,
+            [0xC4, 0x7F, 0x51, 0x8E, 0x12, 0x0A]
+        );
+    }
+
+    #[test]
+    fn parse_stations() {
+        let response = b"\r\n#001,C4:7F:51:01:02:03,192.168.10.2\r\n#002,00:1A:2B:3C:4D:5E,192.168.10.3\r\nOK\r\n> ";
+        let (input, _) = crlf(response).unwrap();
+
+        let (input, first) = station(input).unwrap();
+        assert_eq!(first.mac, [0xC4, 0x7F, 0x51, 0x01, 0x02, 0x03]);
+        assert_eq!(first.ip, IpAddr::V4(Ipv4Addr::new(192, 168, 10, 2)));
+
+        let (input, second) = station(input).unwrap();
+        assert_eq!(second.mac, [0x00, 0x1A, 0x2B, 0x3C, 0x4D, 0x5E]);
+        assert_eq!(second.ip, IpAddr::V4(Ipv4Addr::new(192, 168, 10, 3)));
+
+        assert!(station(input).is_err());
+        assert!(matches!(list_end(input), Ok((b"", ListResponse::Ok))));
+
+        let (_, unnumbered) = station(b"C4:7F:51:01:02:03,192.168.10.2\r\n").unwrap();
+        assert_eq!(unnumbered.ip, IpAddr::V4(Ipv4Addr::new(192, 168, 10, 2)));
+    }
+
+    #[test]
+    fn parse_unknown_security() {
+        let line = b"#001,\"corp\",C4:7F:51:01:02:03,-60,72.0,Infrastructure,WPA2 Enterprise,2.4GHz,36,,\r\n";
+        let (_, result) = scan_result(line).unwrap();
+        assert_eq!(result.security, SecurityType::Unknown);
+        assert_eq!(result.channel, 36);
+    }
+
+    // responses as the module prints them, including the failures seen on real boards
+
+    #[test]
+    fn join_transcripts() {
+        let cases: &[(&[u8], Option<&str>)] = &[
+            (b"\r\n[JOIN   ] drogue,192.168.1.174,0,0\r\nOK\r\n> ", Some("drogue")),
+            (b"\r\n[JOIN   ] guest network,10.0.0.23,0,0\r\nOK\r\n> ", Some("guest network")),
+            (b"\r\n[JOIN   ] drogue\r\n[JOIN   ] Failed\r\nERROR\r\n> ", None),
+            (b"\r\n[JOIN   ] drogue\r\n[JOIN   ] Timeout\r\nERROR\r\n> ", None),
+        ];
+        for (response, ssid) in cases {
+            match (join_response(response), ssid) {
+                (Ok((_, JoinResponse::Ok(result))), Some(ssid)) => assert_eq!(result.ssid.as_str(), *ssid),
+                (Ok((_, JoinResponse::JoinError)), None) => {}
+                (result, _) => panic!("{:?}: unexpected {:?}", response, result),
+            }
+        }
+
+        for response in [
+            &b"\r\n[JOIN   ] drogue,192.168.1.174,0,0\r\nOK\r\n"[..],
+            b"\r\n[JOIN   ] drogue,192.168.1.300,0,0\r\nOK\r\n> ",
+            b"\r\n[JOIN   ] drogue,192.168.1.174,0,1\r\nOK\r\n> ",
+            b"",
+        ].iter() {
+            assert!(join_response(response).is_err(), "{:?}", response);
+        }
+    }
+
+    #[test]
+    fn connect_transcripts() {
+        let cases: &[(&[u8], Option<&[u8]>)] = &[
+            (b"\r\n[TCP  RC] Connecting to 192.168.1.245\r\nOK\r\n> ", None),
+            (b"\r\n[TCP SSL] Connecting to 93.184.216.34\r\nOK\r\n> ", None),
+            (b"\r\nOK\r\n> ", None),
+            (
+                b"\r\n[TCP  RC] Connecting to 192.168.1.245\r\nERROR\r\n> ",
+                Some(b"\r\n[TCP  RC] Connecting to 192.168.1.245\r\n"),
+            ),
+            (
+                b"\r\n[TCP SSL] Connecting to 93.184.216.34\r\nSSL Handshake Failed\r\nERROR\r\n> ",
+                Some(b"\r\n[TCP SSL] Connecting to 93.184.216.34\r\nSSL Handshake Failed\r\n"),
+            ),
+            (b"\r\nERROR\r\n> ", Some(b"\r\n")),
+        ];
+        for (response, reason) in cases {
+            match (connect_response(response), reason) {
+                (Ok((_, ConnectResponse::Ok)), None) => {}
+                (Ok((_, ConnectResponse::Error(actual))), Some(reason)) => assert_eq!(actual, *reason),
+                _ => panic!("{:?}: unexpected result", response),
+            }
+        }
+        assert!(connect_response(b"\r\n[TCP  RC] Connecting to 192.168.1.245\r\n").is_err());
+    }
+
+    #[test]
+    fn tls_failures() {
+        let tls: &[&[u8]] = &[
+            b"\r\n[TCP SSL] Connecting to 93.184.216.34\r\nSSL Handshake Failed\r\n",
+            b"\r\n[TLS   ] handshake failed\r\n",
+            b"\r\nCertificate verify failed\r\n",
+            b"\r\nssl error -0x2700\r\n",
+        ];
+        for reason in tls {
+            assert!(is_tls_failure(reason), "{:?}", reason);
+        }
+
+        let other: &[&[u8]] = &[
+            b"\r\n[TCP  RC] Connecting to 192.168.1.245\r\n",
+            b"\r\nConnection refused\r\n",
+            b"\r\n",
+            b"",
+        ];
+        for reason in other {
+            assert!(!is_tls_failure(reason), "{:?}", reason);
+        }
+    }
+
+    #[test]
+    fn write_transcripts() {
+        let cases: &[(&[u8], Option<usize>)] = &[
+            (b"\r\n12\r\nOK\r\n> ", Some(12)),
+            (b"\r\n1046\r\nOK\r\n> ", Some(1046)),
+            (b"\r\n0\r\nOK\r\n> ", Some(0)),
+            (b"\r\n-1\r\nOK\r\n> ", None),
+        ];
+        for (response, len) in cases {
+            match (write_response(response), len) {
+                (Ok((_, WriteResponse::Ok(actual))), Some(len)) => assert_eq!(actual, *len),
+                (Ok((_, WriteResponse::Error)), None) => {}
+                (result, _) => panic!("{:?}: unexpected {:?}", response, result),
+            }
+        }
+
+        for response in [
+            &b"\r\n99999999999999999999999\r\nOK\r\n> "[..],
+            b"\r\n12\r\nERROR\r\n> ",
+            b"\r\n-2\r\nOK\r\n> ",
+            b"\r\n\r\nOK\r\n> ",
+        ].iter() {
+            assert!(write_response(response).is_err(), "{:?}", response);
+        }
+    }
+
+    #[test]
+    fn read_transcripts() {
+        let cases: &[(&[u8], Option<&[u8]>)] = &[
+            (b"\r\nhowdy\r\nOK\r\n> ", Some(b"howdy")),
+            (b"\r\n\r\nOK\r\n> ", Some(b"")),
+            (
+                b"\r\nHTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n\r\nOK\r\n> ",
+                Some(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n"),
+            ),
+            // the payload contains the response trailer
+            (b"\r\nfirst\r\nOK\r\n> second\r\nOK\r\n> ", Some(b"first\r\nOK\r\n> second")),
+            (b"\r\n\x00\x15\xff\r\nOK\r\n> ", Some(b"\x00\x15\xff")),
+            (b"\r\n-1\r\nOK\r\n> ", None),
+            (b"\r\n-1-1\r\nOK\r\n> ", Some(b"-1-1")),
+            (b"\r\n-1\r\nOK\r\n> -1\r\nOK\r\n> ", Some(b"-1\r\nOK\r\n> -1")),
+            (b"\r\nERROR\r\n> ", None),
+            (b"\r\nSocket not connected\r\nERROR\r\n> ", None),
+        ];
+        for (response, data) in cases {
+            match (read_response(response), data) {
+                (Ok((_, ReadResponse::Ok(actual))), Some(data)) => assert_eq!(actual, *data),
+                (Ok((_, ReadResponse::Err)), None) => {}
+                (result, _) => panic!("{:?}: unexpected {:?}", response, result),
+            }
+        }
+
+        assert!(read_response(b"\r\nhowdy\r\nOK\r\n").is_err());
+        assert!(read_response(b"howdy\r\nOK\r\n> ").is_err());
+    }
+
+    #[test]
+    fn close_transcripts() {
+        assert!(matches!(close_response(b"\r\n\r\nOK\r\n> "), Ok((_, CloseResponse::Ok))));
+        assert!(matches!(close_response(b"\r\nSocket not open\r\nERROR\r\n> "), Ok((_, CloseResponse::Error))));
+        assert!(matches!(close_response(b"\r\nERROR\r\n> "), Ok((_, CloseResponse::Error))));
+        assert!(close_response(b"\r\nOK\r\n> ").is_err());
+    }
+
+    #[test]
+    fn numbers_out_of_range() {
+        assert_eq!(parse_u8(b"255,").unwrap().1, 255);
+        assert!(parse_u8(b"256,").is_err());
+        assert!(parse_u16(b"65536").is_err());
+        assert!(parse_usize(b"184467440737095516160").is_err());
+
+        let line = b"#001,\"drogue\",C4:7F:51:01:02:03,-52,72.0,Infrastructure,WPA2 AES,2.4GHz,999,,\r\n";
+        assert!(scan_result(line).is_err());
+        assert_eq!(rssi(b"-99999999999999999999999,").ok(), None);
+    }
+
+    /// Every parser the arbiter hands a module response to.
+    fn parse_all(input: &[u8]) {
+        let _ = join_response(input);
+        let _ = connect_response(input);
+        let _ = close_response(input);
+        let _ = resolve_response(input);
+        let _ = command_response(input);
+        let _ = message_response(input);
+        let _ = write_response(input);
+        let _ = read_response(input);
+        let _ = network_settings(input);
+        let _ = rssi_response(input);
+        let _ = mac_response(input);
+        let _ = scan_result(input);
+        let _ = station(input);
+        let _ = list_end(input);
+        let _ = accepted(input);
+        let _ = socket_status(input);
+        let _ = module_error(input);
+    }
+
+    mod properties {
+        extern crate std;
+
+        use super::*;
+        use std::vec::Vec;
+        use proptest::prelude::*;
+        use proptest::collection::vec;
+
+        // favour the bytes the parsers look for, so they get past the first tag
+        fn response() -> impl Strategy<Value = Vec<u8>> {
+            vec(
+                prop_oneof![
+                    any::<u8>(),
+                    prop::sample::select(&b"\r\n>OK ERROR,.:#\"-0123456789[]"[..]),
+                ],
+                0..256,
+            )
+        }
+
+        proptest! {
+            #[test]
+            fn parsers_never_panic(input in response()) {
+                parse_all(&input);
+            }
+
+            #[test]
+            fn framed_responses_never_panic(body in response()) {
+                let mut input = b"\r\n".to_vec();
+                input.extend_from_slice(&body);
+                input.extend_from_slice(b"\r\nOK\r\n> ");
+                parse_all(&input);
+            }
+
+            #[test]
+            fn read_round_trip(data in vec(any::<u8>(), 0..1460)) {
+                prop_assume!(data != b"-1");
+                let mut input = b"\r\n".to_vec();
+                input.extend_from_slice(&data);
+                input.extend_from_slice(b"\r\nOK\r\n> ");
+                match read_response(&input) {
+                    Ok((rest, ReadResponse::Ok(actual))) => {
+                        prop_assert_eq!(actual, &data[..]);
+                        prop_assert!(rest.is_empty());
+                    }
+                    result => prop_assert!(false, "unexpected {:?}", result),
+                }
+            }
+
+            #[test]
+            fn write_round_trip(len in any::<usize>()) {
+                let input = std::format!("\r\n{}\r\nOK\r\n> ", len);
+                prop_assert!(matches!(write_response(input.as_bytes()), Ok((_, WriteResponse::Ok(actual))) if actual == len));
+            }
+
+            #[test]
+            fn module_error_fits(input in response()) {
+                prop_assert!(module_error(&input).message().len() <= 64);
+            }
+        }
+    }
+}
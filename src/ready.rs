@@ -15,8 +15,8 @@ impl<Pin> Ready<Pin>
         }
     }
 
-    pub(crate) fn is_ready(&self) -> bool {
-        self.pin.is_high().unwrap_or(false)
+    pub(crate) fn is_ready(&self) -> Result<bool, Pin::Error> {
+        self.pin.is_high()
     }
 
-}
\ No newline at end of file
+}
@@ -1,25 +1,27 @@
 use drogue_network::tcp::Mode;
+use drogue_network::addr::SocketAddr;
 
 pub(crate) enum State {
     Closed,
     Open,
     Connected,
     HalfClosed,
+    Listening,
 }
 
 pub(crate) struct Socket {
     pub(crate) state: State,
     pub(crate) mode: Mode,
+    /// The client address of a connection accepted by a server.
+    pub(crate) remote: Option<SocketAddr>,
+    /// The arbiter's `generation()` when the socket was connected or
+    /// started listening on the module.
+    pub(crate) generation: u32,
 }
 
 impl Socket {
-    pub(crate) fn create() -> [Socket; 4] {
-        [
-            Socket::default(),
-            Socket::default(),
-            Socket::default(),
-            Socket::default(),
-        ]
+    pub(crate) fn create<const N: usize>() -> [Socket; N] {
+        core::array::from_fn(|_| Socket::default())
     }
 
     pub(crate) fn is_connected(&self) -> bool {
@@ -30,21 +32,22 @@ impl Socket {
         matches!(&self.state, State::Closed) || matches!(&self.state, State::HalfClosed)
     }
 
+    /// The peer closed the connection, but data it sent may still be buffered by the module.
+    pub(crate) fn is_half_closed(&self) -> bool {
+        matches!(&self.state, State::HalfClosed)
+    }
+
     pub(crate) fn is_open(&self) -> bool {
         matches!(&self.state, State::Open) || self.is_connected()
     }
 
-    pub(crate) fn is_blocking(&self) -> bool {
-        matches!(&self.mode, Mode::Blocking)
+    pub(crate) fn is_listening(&self) -> bool {
+        matches!(&self.state, State::Listening)
     }
 
     pub(crate) fn is_non_blocking(&self) -> bool {
         matches!(&self.mode, Mode::NonBlocking)
     }
-
-    pub(crate) fn is_timeout(&self) -> bool {
-        matches!(&self.mode, Mode::Timeout(_))
-    }
 }
 
 impl Default for Socket {
@@ -52,7 +55,18 @@ impl Default for Socket {
         Self {
             state: State::Closed,
             mode: Mode::Blocking,
+            remote: None,
+            generation: 0,
         }
     }
 
 }
+
+/// `Mode` is neither `Clone` nor `Copy`.
+pub(crate) fn copy_mode(mode: &Mode) -> Mode {
+    match mode {
+        Mode::Blocking => Mode::Blocking,
+        Mode::NonBlocking => Mode::NonBlocking,
+        Mode::Timeout(ms) => Mode::Timeout(*ms),
+    }
+}
@@ -0,0 +1,1064 @@
+use drogue_es_wifi::adapter::{
+    AccessPointConfig, AccessPointSecurity, Adapter, AdapterConfig, CertificateKind, ControlPin, Error, Event, ScanResult, Station,
+    TlsConfig,
+};
+use drogue_es_wifi::network;
+use drogue_es_wifi::mock::{MockClock, MockError, MockModule, MockPin, MockSpi};
+use drogue_network::addr::{HostAddr, HostSocketAddr, IpAddr, Ipv4Addr, SocketAddr};
+use drogue_network::tcp::{Mode, TcpStack};
+use heapless::consts::U8;
+use proptest::prelude::*;
+
+type MockAdapter<'a> = Adapter<'a, MockSpi<'a>, MockPin<'a>, MockPin<'a>, MockPin<'a>, MockPin<'a>, MockClock>;
+
+const JOINED: &[u8] = b"\r\n[JOIN   ] drogue,192.168.1.174,0,0\r\nOK\r\n> ";
+
+fn adapter<'a>(module: &'a MockModule, clock: &'a MockClock) -> MockAdapter<'a> {
+    let mut adapter = Adapter::new(
+        module.spi(),
+        module.cs(),
+        module.ready(),
+        module.wakeup(),
+        module.reset(),
+        clock,
+        AdapterConfig::default(),
+    ).unwrap();
+    adapter.init().unwrap();
+    adapter
+}
+
+fn remote() -> HostSocketAddr {
+    HostSocketAddr::new(HostAddr::new(IpAddr::V4(Ipv4Addr::new(192, 168, 1, 245)), None), 8080)
+}
+
+#[test]
+fn init() {
+    let module = MockModule::new();
+    let clock = MockClock::new();
+    adapter(&module, &clock);
+    assert!(module.sent("MT=1"));
+}
+
+#[test]
+fn conservative_timing() {
+    let module = MockModule::new();
+    let clock = MockClock::new();
+    let mut adapter: MockAdapter = Adapter::new(
+        module.spi(),
+        module.cs(),
+        module.ready(),
+        module.wakeup(),
+        module.reset(),
+        &clock,
+        AdapterConfig::conservative(),
+    ).unwrap();
+    adapter.init().unwrap();
+
+    module.respond("C0", JOINED);
+    adapter.join_wpa2("drogue", "rodneygnome").unwrap();
+}
+
+/// An error type of another HAL, unrelated to the mock's.
+#[derive(Debug)]
+struct OtherError;
+
+/// A ready pin whose HAL reports its own error type.
+struct ReadyPin<'a>(MockPin<'a>);
+
+impl embedded_hal::digital::v2::InputPin for ReadyPin<'_> {
+    type Error = OtherError;
+
+    fn is_high(&self) -> Result<bool, Self::Error> {
+        self.0.is_high().map_err(|_| OtherError)
+    }
+
+    fn is_low(&self) -> Result<bool, Self::Error> {
+        self.0.is_low().map_err(|_| OtherError)
+    }
+}
+
+/// A reset pin which can't be driven.
+struct BrokenPin;
+
+impl embedded_hal::digital::v2::OutputPin for BrokenPin {
+    type Error = OtherError;
+
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        Err(OtherError)
+    }
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        Err(OtherError)
+    }
+}
+
+#[test]
+fn mixed_pin_errors() {
+    let module = MockModule::new();
+    let clock = MockClock::new();
+    let mut adapter = Adapter::new(
+        module.spi(),
+        module.cs(),
+        ReadyPin(module.ready()),
+        module.wakeup(),
+        module.reset(),
+        &clock,
+        AdapterConfig::default(),
+    ).unwrap();
+    adapter.init().unwrap();
+
+    module.respond("C0", JOINED);
+    adapter.join_wpa2("drogue", "rodneygnome").unwrap();
+
+    // a failing pin is named, whatever its error type
+    let mut adapter = Adapter::new(
+        module.spi(),
+        module.cs(),
+        module.ready(),
+        module.wakeup(),
+        BrokenPin,
+        &clock,
+        AdapterConfig::default(),
+    ).unwrap();
+    assert!(matches!(adapter.init(), Err(Error::ControlPin(ControlPin::Reset))));
+}
+
+#[test]
+fn join() {
+    let module = MockModule::new();
+    let clock = MockClock::new();
+    module.respond("C0", JOINED);
+
+    let mut adapter = adapter(&module, &clock);
+    let result = adapter.join_wpa2("drogue", "rodneygnome").unwrap();
+
+    assert_eq!(result.ssid.as_str(), "drogue");
+    assert_eq!(result.ip, IpAddr::V4(Ipv4Addr::new(192, 168, 1, 174)));
+    assert!(module.sent("C1=drogue"));
+    assert!(module.sent("C2=rodneygnome"));
+    assert!(module.sent("C3=3"));
+    assert!(module.sent("C4=1"));
+}
+
+#[test]
+fn join_failure() {
+    let module = MockModule::new();
+    let clock = MockClock::new();
+    module.respond("C0", b"\r\n[JOIN   ] drogue\r\n[JOIN   ] Failed\r\nERROR\r\n> ");
+
+    let mut adapter = adapter(&module, &clock);
+    match adapter.join_wpa2("drogue", "rodneygnome") {
+        Err(Error::UnableToAssociate(e)) => assert_eq!(e.message(), "[JOIN   ] drogue; [JOIN   ] Failed"),
+        other => panic!("expected UnableToAssociate, got {:?}", other),
+    }
+}
+
+#[test]
+fn join_invalid() {
+    let module = MockModule::new();
+    let clock = MockClock::new();
+    let mut adapter = adapter(&module, &clock);
+    module.clear_sent();
+
+    let long = "drogue-drogue-drogue-drogue-drogue";
+    assert!(matches!(adapter.join_open(""), Err(Error::InvalidSsid)));
+    assert!(matches!(adapter.join_open(long), Err(Error::InvalidSsid)));
+    assert!(matches!(adapter.join_open("drogue\rC0"), Err(Error::InvalidSsid)));
+    assert!(matches!(adapter.join_wep("", "secret"), Err(Error::InvalidSsid)));
+    assert!(matches!(adapter.join_wep(long, "secret"), Err(Error::InvalidSsid)));
+    assert!(matches!(adapter.join_wep("drogue", long), Err(Error::InvalidPassword)));
+    assert!(matches!(adapter.join_wep("drogue", "secret\rC0"), Err(Error::InvalidPassword)));
+    assert!(matches!(adapter.join_wpa2("drogue\r", "rodneygnome"), Err(Error::InvalidSsid)));
+    assert!(matches!(adapter.join_wpa2("drogue", "rodney\rC0"), Err(Error::InvalidPassword)));
+    assert!(matches!(adapter.join_wpa2("drogue", &"x".repeat(64)), Err(Error::InvalidPassword)));
+    assert!(!module.sent("C"));
+}
+
+#[test]
+fn connect_write_read() {
+    let module = MockModule::new();
+    let clock = MockClock::new();
+    let adapter = adapter(&module, &clock);
+
+    let socket = adapter.open(Mode::Blocking).unwrap();
+    let mut socket = adapter.connect(socket, remote()).unwrap();
+    assert!(module.sent("P3=192.168.1.245"));
+    assert!(module.sent("P4=8080"));
+
+    assert_eq!(adapter.write(&mut socket, b"hello, world").unwrap(), 12);
+    assert_eq!(&module.written()[..], b"hello, world");
+
+    module.receive_data(b"howdy");
+    let mut buffer = [0; 5];
+    assert_eq!(adapter.read(&mut socket, &mut buffer).unwrap(), 5);
+    assert_eq!(&buffer, b"howdy");
+
+    adapter.close(socket).unwrap();
+    assert!(module.sent("P6=0"));
+}
+
+#[test]
+fn connect_refused() {
+    let module = MockModule::new();
+    let clock = MockClock::new();
+    module.respond("P6=1", b"\r\n[TCP  RC] Connecting to 192.168.1.245\r\nERROR\r\n> ");
+
+    let adapter = adapter(&module, &clock);
+    let socket = adapter.open(Mode::Blocking).unwrap();
+    match adapter.connect(socket, remote()) {
+        Err(Error::ConnectionFailed(e)) => assert_eq!(e.message(), "[TCP  RC] Connecting to 192.168.1.245"),
+        other => panic!("expected ConnectionFailed, got {:?}", other),
+    }
+}
+
+#[test]
+fn load_certificate() {
+    let module = MockModule::new();
+    let clock = MockClock::new();
+    let mut adapter = adapter(&module, &clock);
+
+    adapter.load_certificate(CertificateKind::CaCertificate, 1, b"-----BEGIN CERTIFICATE-----").unwrap();
+    assert!(module.sent("PG=0,1,27"));
+
+    module.clear_sent();
+    assert!(matches!(adapter.load_certificate(CertificateKind::PrivateKey, 1, b""), Err(Error::EmptyCertificate)));
+    assert!(!module.sent("PG"));
+
+    module.respond("PG=", b"\r\nERROR\r\n> ");
+    assert!(matches!(adapter.load_certificate(CertificateKind::ClientCertificate, 2, b"key"), Err(Error::Module(_))));
+}
+
+#[test]
+fn connect_tls() {
+    let module = MockModule::new();
+    let clock = MockClock::new();
+    let adapter = adapter(&module, &clock);
+
+    let socket = adapter.open(Mode::Blocking).unwrap();
+    adapter.connect_tls(socket, remote(), &TlsConfig { slot: 1, verify_server: true }).unwrap();
+    assert!(module.sent_in_order(&["P9=2", "PF=1", "P1=3", "P3=192.168.1.245", "P4=8080", "P6=1"]));
+
+    module.clear_sent();
+    let socket = adapter.open(Mode::Blocking).unwrap();
+    adapter.connect_tls(socket, remote(), &TlsConfig { slot: 0, verify_server: false }).unwrap();
+    assert!(module.sent_in_order(&["P9=0", "PF=0", "P1=3", "P6=1"]));
+}
+
+#[test]
+fn connect_tls_failures() {
+    let module = MockModule::new();
+    let clock = MockClock::new();
+    let adapter = adapter(&module, &clock);
+    let tls = TlsConfig { slot: 1, verify_server: true };
+
+    module.respond_once("P6=1", b"\r\n[TCP SSL] Connecting to 192.168.1.245\r\nSSL Handshake Failed\r\nERROR\r\n> ");
+    let socket = adapter.open(Mode::Blocking).unwrap();
+    match adapter.connect_tls(socket, remote(), &tls) {
+        Err(Error::TlsHandshakeFailed(_)) => {}
+        other => panic!("expected TlsHandshakeFailed, got {:?}", other),
+    }
+
+    module.respond_once("P6=1", b"\r\n[TCP  RC] Connecting to 192.168.1.245\r\nERROR\r\n> ");
+    let socket = adapter.open(Mode::Blocking).unwrap();
+    match adapter.connect_tls(socket, remote(), &tls) {
+        Err(Error::ConnectionFailed(_)) => {}
+        other => panic!("expected ConnectionFailed, got {:?}", other),
+    }
+}
+
+#[test]
+fn write_error() {
+    let module = MockModule::new();
+    let clock = MockClock::new();
+    module.respond("S0", b"\r\n-1\r\nOK\r\n> ");
+
+    let adapter = adapter(&module, &clock);
+    let socket = adapter.open(Mode::Blocking).unwrap();
+    let mut socket = adapter.connect(socket, remote()).unwrap();
+    match adapter.write(&mut socket, b"hello") {
+        Err(nb::Error::Other(Error::WriteFailed(e))) => assert_eq!(e.code(), Some(-1)),
+        other => panic!("expected WriteFailed, got {:?}", other),
+    }    assert!(adapter.is_connected(&socket).unwrap());
+}
+
+#[test]
+fn spi_error() {
+    let module = MockModule::new();
+    let clock = MockClock::new();
+    let mut adapter = adapter(&module, &clock);
+
+    module.fail_transfers(true);
+    assert!(matches!(adapter.join_open("drogue"), Err(Error::Spi(MockError))));
+
+    module.fail_transfers(false);
+    module.respond("C0", JOINED);
+    assert!(adapter.join_open("drogue").is_ok());
+}
+
+#[test]
+fn read_spi_error() {
+    let module = MockModule::new();
+    let clock = MockClock::new();
+    let adapter = adapter(&module, &clock);
+
+    let socket = adapter.open(Mode::NonBlocking).unwrap();
+    let mut socket = adapter.connect(socket, remote()).unwrap();
+    let mut buffer = [0; 8];
+
+    // a bus glitch is no reason to give up on the connection
+    module.fail_transfers(true);
+    assert!(matches!(adapter.read(&mut socket, &mut buffer), Err(nb::Error::Other(Error::Spi(MockError)))));
+    module.fail_transfers(false);
+
+    assert!(adapter.is_connected(&socket).unwrap());
+    module.receive_data(b"howdy");
+    assert_eq!(adapter.read(&mut socket, &mut buffer).unwrap(), 5);
+}
+
+#[test]
+fn close_failure() {
+    let module = MockModule::new();
+    let clock = MockClock::new();
+    let adapter = adapter(&module, &clock);
+
+    let socket = adapter.open(Mode::Blocking).unwrap();
+    let socket = adapter.connect(socket, remote()).unwrap();
+    let other = adapter.open(Mode::Blocking).unwrap();
+    let other = adapter.connect(other, remote()).unwrap();
+
+    module.respond_once("P6=0", b"\r\nERROR\r\n> ");
+    assert!(matches!(adapter.close(socket), Err(Error::Module(_))));
+
+    // the module may still hold the socket, so it is reset along with the others
+    assert!(matches!(adapter.poll_event(), Some(Event::Reset)));
+    assert!(!adapter.is_connected(&other).unwrap());
+
+    module.clear_sent();
+    let socket = adapter.open(Mode::Blocking).unwrap();
+    assert_eq!(socket.id(), 0);
+    let socket = adapter.connect(socket, remote()).unwrap();
+    assert!(module.sent("MT=1"));
+    assert!(adapter.is_connected(&socket).unwrap());
+}
+
+#[test]
+fn leave_close_failure() {
+    let module = MockModule::new();
+    let clock = MockClock::new();
+    let mut adapter = adapter(&module, &clock);
+
+    let socket = adapter.open(Mode::Blocking).unwrap();
+    adapter.connect(socket, remote()).unwrap();
+    module.respond_once("P6=0", b"\r\nERROR\r\n> ");
+    adapter.leave().unwrap();
+
+    // sockets opened after the reset are not invalidated by it
+    let socket = adapter.open(Mode::Blocking).unwrap();
+    let socket = adapter.connect(socket, remote()).unwrap();
+    assert!(adapter.is_connected(&socket).unwrap());
+    assert!(matches!(adapter.poll_event(), Some(Event::Reset)));
+    assert!(adapter.is_connected(&socket).unwrap());
+}
+
+#[test]
+fn unresponsive() {
+    let module = MockModule::new();
+    let clock = MockClock::new();
+    let adapter = adapter(&module, &clock);
+
+    let socket = adapter.open(Mode::Blocking).unwrap();
+    let mut socket = adapter.connect(socket, remote()).unwrap();
+    let mut buffer = [0; 8];
+
+    module.hang();
+    assert!(matches!(adapter.read(&mut socket, &mut buffer), Err(nb::Error::Other(Error::Unresponsive))));
+
+    // the module is reset by the next request, and the old socket is gone
+    let other = adapter.open(Mode::Blocking).unwrap();
+    let other = adapter.connect(other, remote()).unwrap();
+    assert!(adapter.is_connected(&other).unwrap());
+    assert!(!adapter.is_connected(&socket).unwrap());
+    assert!(matches!(adapter.poll_event(), Some(Event::Reset)));
+    assert!(adapter.is_connected(&other).unwrap());
+}
+
+#[test]
+fn peer_closed() {
+    let module = MockModule::new();
+    let clock = MockClock::new();
+    let adapter = adapter(&module, &clock);
+
+    let socket = adapter.open(Mode::Blocking).unwrap();
+    let mut socket = adapter.connect(socket, remote()).unwrap();
+
+    module.close_peer(0);
+    adapter.write(&mut socket, b"hello").unwrap();
+
+    assert!(matches!(adapter.poll_event(), Some(Event::PeerClosed { socket: 0 })));
+    assert!(matches!(adapter.write(&mut socket, b"hello"), Err(nb::Error::Other(Error::SocketNotOpen))));
+}
+
+#[test]
+fn peer_closed_other_socket() {
+    let module = MockModule::new();
+    let clock = MockClock::new();
+    let adapter = adapter(&module, &clock);
+
+    let socket = adapter.open(Mode::NonBlocking).unwrap();
+    let mut socket = adapter.connect(socket, remote()).unwrap();
+    let other = adapter.open(Mode::NonBlocking).unwrap();
+    let other = adapter.connect(other, remote()).unwrap();
+
+    // the message doesn't say which socket closed, so the module is asked
+    module.close_peer(1);
+    assert!(matches!(adapter.read(&mut socket, &mut [0; 8]), Err(nb::Error::WouldBlock)));
+    assert!(module.sent("P?"));
+
+    assert!(matches!(adapter.poll_event(), Some(Event::PeerClosed { socket: 1 })));
+    assert!(adapter.poll_event().is_none());
+    assert!(adapter.is_connected(&socket).unwrap());
+    assert!(!adapter.is_connected(&other).unwrap());
+}
+
+#[test]
+fn poll_event() {
+    let module = MockModule::new();
+    let clock = MockClock::new();
+    let adapter = adapter(&module, &clock);
+
+    // events that concern no socket request arrive all the same
+    module.message("[WIFI   ] Disconnected");
+    assert!(matches!(adapter.poll_event(), Some(Event::Disconnected)));
+    assert!(adapter.poll_event().is_none());
+
+    let socket = adapter.open(Mode::NonBlocking).unwrap();
+    let mut listener = adapter.listen(socket, 8080, 1).unwrap();
+    let socket = adapter.open(Mode::NonBlocking).unwrap();
+    let mut other = adapter.listen(socket, 8081, 1).unwrap();
+
+    module.accept_client(1, "192.168.1.2:52344");
+    match adapter.poll_event() {
+        Some(Event::Accepted { socket: 1, remote }) => {
+            assert_eq!(remote, SocketAddr::new(IpAddr::V4(Ipv4Addr::new(192, 168, 1, 2)), 52344))
+        }
+        other => panic!("expected Accepted on socket 1, got {:?}", other),
+    }
+    assert!(matches!(adapter.accept(&mut listener), Err(nb::Error::WouldBlock)));
+    let (client, _) = adapter.accept(&mut other).unwrap();
+    assert_eq!(client.id(), 1);
+}
+
+#[test]
+fn listen_accept() {
+    let module = MockModule::new();
+    let clock = MockClock::new();
+    let adapter = adapter(&module, &clock);
+
+    let socket = adapter.open(Mode::NonBlocking).unwrap();
+    let mut listener = adapter.listen(socket, 8080, 2).unwrap();
+    assert!(module.sent_in_order(&["P0=0", "P1=0", "P2=8080", "P8=2", "P5=1"]));
+    assert!(matches!(adapter.accept(&mut listener), Err(nb::Error::WouldBlock)));
+
+    module.clear_sent();
+    module.message("Accepted 192.168.1.2:52344");
+    let (mut client, remote) = adapter.accept(&mut listener).unwrap();
+    assert_eq!(client.id(), 0);
+    assert_eq!(remote, SocketAddr::new(IpAddr::V4(Ipv4Addr::new(192, 168, 1, 2)), 52344));
+
+    // the listener moves on to the next free socket
+    assert!(module.sent_in_order(&["P0=1", "P2=8080", "P5=1"]));
+    assert!(adapter.is_connected(&client).unwrap());
+    assert_eq!(adapter.write(&mut client, b"hello").unwrap(), 5);
+
+    module.message("Accepted 192.168.1.3:52345");
+    let (client, _) = adapter.accept(&mut listener).unwrap();
+    assert_eq!(client.id(), 1);
+}
+
+#[test]
+fn accept_no_available_sockets() {
+    let module = MockModule::new();
+    let clock = MockClock::new();
+    let adapter = adapter(&module, &clock);
+
+    let socket = adapter.open(Mode::NonBlocking).unwrap();
+    let mut listener = adapter.listen(socket, 8080, 1).unwrap();
+    let mut others = Vec::new();
+    for _ in 1..4 {
+        let socket = adapter.open(Mode::NonBlocking).unwrap();
+        others.push(adapter.connect(socket, remote()).unwrap());
+    }
+
+    module.message("Accepted 192.168.1.2:52344");
+    let (client, _) = adapter.accept(&mut listener).unwrap();
+    assert_eq!(client.id(), 0);
+    assert!(matches!(adapter.accept(&mut listener), Err(nb::Error::Other(Error::NoAvailableSockets))));
+
+    // closing a socket lets the listener take it over
+    module.clear_sent();
+    adapter.close(others.pop().unwrap()).unwrap();
+    assert!(matches!(adapter.accept(&mut listener), Err(nb::Error::WouldBlock)));
+    assert!(module.sent_in_order(&["P6=0", "P2=8080", "P5=1"]));
+}
+
+#[test]
+fn accept_while_reading() {
+    let module = MockModule::new();
+    let clock = MockClock::new();
+    let adapter = adapter(&module, &clock);
+
+    let socket = adapter.open(Mode::NonBlocking).unwrap();
+    let mut listener = adapter.listen(socket, 8080, 1).unwrap();
+    let socket = adapter.open(Mode::NonBlocking).unwrap();
+    let mut socket = adapter.connect(socket, remote()).unwrap();
+
+    // the client arrives while the other socket is being read
+    module.message("Accepted 192.168.1.2:52344");
+    assert!(matches!(adapter.read(&mut socket, &mut [0; 8]), Err(nb::Error::WouldBlock)));
+    assert!(adapter.is_connected(&socket).unwrap());
+
+    let (client, remote) = adapter.accept(&mut listener).unwrap();
+    assert_eq!(client.id(), 0);
+    assert_eq!(remote, SocketAddr::new(IpAddr::V4(Ipv4Addr::new(192, 168, 1, 2)), 52344));
+}
+
+#[test]
+fn read_binary() {
+    let module = MockModule::new();
+    let clock = MockClock::new();
+    let adapter = adapter(&module, &clock);
+
+    let socket = adapter.open(Mode::NonBlocking).unwrap();
+    let mut socket = adapter.connect(socket, remote()).unwrap();
+
+    // pad bytes and the module's own trailer, at both odd and even offsets
+    let data = b"\x15\x00\x15\r\nOK\r\n> \x15\x15\xff\r\nERROR\r\n> \x15";
+    module.receive_data(data);
+    let mut buffer = [0; 32];
+    assert_eq!(adapter.read(&mut socket, &mut buffer).unwrap(), data.len());
+    assert_eq!(&buffer[..data.len()], &data[..]);
+}
+
+#[test]
+fn read_error() {
+    let module = MockModule::new();
+    let clock = MockClock::new();
+    module.respond("R0", b"\r\nSocket not connected\r\nERROR\r\n> ");
+
+    let adapter = adapter(&module, &clock);
+    let socket = adapter.open(Mode::NonBlocking).unwrap();
+    let mut socket = adapter.connect(socket, remote()).unwrap();
+    let mut buffer = [0; 8];
+    match adapter.read(&mut socket, &mut buffer) {
+        Err(nb::Error::Other(Error::ReadFailed(e))) => assert_eq!(e.message(), "Socket not connected"),
+        other => panic!("expected ReadFailed, got {:?}", other),
+    }
+}
+
+#[test]
+fn read_failure() {
+    let module = MockModule::new();
+    let clock = MockClock::new();
+    module.respond("R0", b"\r\n-1\r\nOK\r\n> ");
+
+    let adapter = adapter(&module, &clock);
+    let socket = adapter.open(Mode::NonBlocking).unwrap();
+    let mut socket = adapter.connect(socket, remote()).unwrap();
+    let mut buffer = [0; 8];
+    match adapter.read(&mut socket, &mut buffer) {
+        Err(nb::Error::Other(Error::ReadFailed(e))) => assert_eq!(e.message(), "-1"),
+        other => panic!("expected ReadFailed, got {:?}", other),
+    }
+    // the module still reports the socket connected
+    assert!(module.sent("P?"));
+    assert!(adapter.is_connected(&socket).unwrap());
+
+    module.respond_once("P?", b"\r\n0,0,192.168.1.174,49152,192.168.1.245,8080,0,0,0\r\nOK\r\n> ");
+    assert!(matches!(adapter.read(&mut socket, &mut buffer), Err(nb::Error::Other(Error::ReadFailed(_)))));
+    assert!(!adapter.is_connected(&socket).unwrap());
+    assert_eq!(adapter.read(&mut socket, &mut buffer).unwrap(), 0);
+}
+
+#[test]
+fn read_setting_rejected() {
+    let module = MockModule::new();
+    let clock = MockClock::new();
+    module.respond("R1=0", b"\r\nERROR\r\n> ");
+
+    let adapter = adapter(&module, &clock);
+    let socket = adapter.open(Mode::NonBlocking).unwrap();
+    let mut socket = adapter.connect(socket, remote()).unwrap();
+    module.clear_sent();
+    assert_eq!(adapter.read(&mut socket, &mut []).unwrap(), 0);
+    assert!(!module.sent("R0"));
+
+    module.respond_once("R1=", b"\r\nERROR\r\n> ");
+    assert!(matches!(adapter.read(&mut socket, &mut [0; 8]), Err(nb::Error::Other(Error::Module(_)))));
+    assert!(adapter.is_connected(&socket).unwrap());
+}
+
+#[test]
+fn read_too_long() {
+    let module = MockModule::new();
+    let clock = MockClock::new();
+    module.respond("R0", b"\r\nmore than asked for\r\nOK\r\n> ");
+
+    let adapter = adapter(&module, &clock);
+    let socket = adapter.open(Mode::NonBlocking).unwrap();
+    let mut socket = adapter.connect(socket, remote()).unwrap();
+    let mut buffer = [0; 4];
+    assert!(matches!(adapter.read(&mut socket, &mut buffer), Err(nb::Error::Other(Error::UnexpectedResponse))));
+}
+
+#[test]
+fn write_large() {
+    let module = MockModule::new();
+    let clock = MockClock::new();
+    let adapter = adapter(&module, &clock);
+
+    let socket = adapter.open(Mode::Blocking).unwrap();
+    let mut socket = adapter.connect(socket, remote()).unwrap();
+
+    let data: Vec<u8> = (0..3001).map(|i| i as u8).collect();
+    assert_eq!(adapter.write(&mut socket, &data).unwrap(), data.len());
+    assert_eq!(&module.written()[..], &data[..]);
+    assert!(module.sent("S1=1046"));
+    assert!(module.sent("S1=909"));
+}
+
+#[test]
+fn write_short() {
+    let module = MockModule::new();
+    let clock = MockClock::new();
+    let adapter = adapter(&module, &clock);
+
+    let socket = adapter.open(Mode::Blocking).unwrap();
+    let mut socket = adapter.connect(socket, remote()).unwrap();
+
+    module.clear_sent();
+    assert_eq!(adapter.write(&mut socket, b"").unwrap(), 0);
+    assert!(!module.sent("S0"));
+
+    assert_eq!(adapter.write(&mut socket, b"a").unwrap(), 1);
+    assert_eq!(adapter.write(&mut socket, b"bcd").unwrap(), 3);
+    assert_eq!(&module.written()[..], b"abcd");
+}
+
+#[test]
+fn write_partial() {
+    let module = MockModule::new();
+    let clock = MockClock::new();
+    let adapter = adapter(&module, &clock);
+
+    let socket = adapter.open(Mode::NonBlocking).unwrap();
+    let mut socket = adapter.connect(socket, remote()).unwrap();
+
+    module.respond_once("S0", b"\r\n500\r\nOK\r\n> ");
+    assert_eq!(adapter.write(&mut socket, &[0; 2000]).unwrap(), 500);
+
+    module.respond_once("S0", b"\r\n0\r\nOK\r\n> ");
+    assert!(matches!(adapter.write(&mut socket, b"hello"), Err(nb::Error::WouldBlock)));
+}
+
+#[test]
+fn connected() {
+    let module = MockModule::new();
+    let clock = MockClock::new();
+    let adapter = adapter(&module, &clock);
+
+    let socket = adapter.open(Mode::Blocking).unwrap();
+    assert!(!adapter.is_connected(&socket).unwrap());
+    let socket = adapter.connect(socket, remote()).unwrap();
+    assert!(adapter.is_connected(&socket).unwrap());
+
+    module.close_peer(0);
+    assert!(!adapter.is_connected(&socket).unwrap());
+}
+
+#[test]
+fn read_to_end() {
+    let module = MockModule::new();
+    let clock = MockClock::new();
+    let adapter = adapter(&module, &clock);
+
+    let socket = adapter.open(Mode::Blocking).unwrap();
+    let mut socket = adapter.connect(socket, remote()).unwrap();
+
+    module.receive_data(b"goodbye");
+    module.close_peer(0);
+
+    let mut buffer = [0; 4];
+    assert_eq!(adapter.read(&mut socket, &mut buffer).unwrap(), 4);
+    assert_eq!(&buffer, b"good");
+    assert_eq!(adapter.read(&mut socket, &mut buffer).unwrap(), 3);
+    assert_eq!(&buffer[..3], b"bye");
+    assert_eq!(adapter.read(&mut socket, &mut buffer).unwrap(), 0);
+    assert_eq!(adapter.read(&mut socket, &mut buffer).unwrap(), 0);
+
+    assert!(matches!(adapter.write(&mut socket, b"hello"), Err(nb::Error::Other(Error::SocketNotOpen))));
+}
+
+fn udp_remote() -> SocketAddr {
+    SocketAddr::new(IpAddr::V4(Ipv4Addr::new(192, 168, 1, 245)), 5683)
+}
+
+#[test]
+fn udp() {
+    let module = MockModule::new();
+    let clock = MockClock::new();
+    let adapter = adapter(&module, &clock);
+
+    let mut socket = network::UdpStack::open(&adapter, udp_remote(), Mode::Timeout(1000)).unwrap();
+    assert!(module.sent("P1=1"));
+    assert!(module.sent("P3=192.168.1.245"));
+    assert!(module.sent("P4=5683"));
+    assert!(module.sent("P6=1"));
+
+    network::UdpStack::write(&adapter, &mut socket, b"hello").unwrap();
+    assert_eq!(&module.written()[..], b"hello");
+
+    module.receive_data(b"howdy");
+    let mut buffer = [0; 16];
+    assert_eq!(network::UdpStack::read(&adapter, &mut socket, &mut buffer).unwrap(), 5);
+    assert_eq!(&buffer[..5], b"howdy");
+
+    // unlike an empty datagram, an expired timeout is an error
+    assert!(matches!(network::UdpStack::read(&adapter, &mut socket, &mut buffer), Err(nb::Error::Other(Error::Timeout))));
+
+    module.clear_sent();
+    network::UdpStack::close(&adapter, socket).unwrap();
+    assert!(module.sent("P6=0"));
+}
+
+#[test]
+fn udp_write_errors() {
+    let module = MockModule::new();
+    let clock = MockClock::new();
+    let adapter = adapter(&module, &clock);
+
+    let mut socket = network::UdpStack::open(&adapter, udp_remote(), Mode::NonBlocking).unwrap();
+
+    assert!(matches!(network::UdpStack::write(&adapter, &mut socket, b""), Err(nb::Error::Other(Error::EmptyDatagram))));
+    assert!(matches!(network::UdpStack::write(&adapter, &mut socket, &[0; 1047]), Err(nb::Error::Other(Error::DatagramTooLarge))));
+
+    module.respond_once("S0", b"\r\n0\r\nOK\r\n> ");
+    assert!(matches!(network::UdpStack::write(&adapter, &mut socket, b"hello"), Err(nb::Error::WouldBlock)));
+
+    module.respond_once("S0", b"\r\n3\r\nOK\r\n> ");
+    assert!(matches!(network::UdpStack::write(&adapter, &mut socket, b"hello"), Err(nb::Error::Other(Error::WriteFailed(_)))));
+
+    network::UdpStack::write(&adapter, &mut socket, b"hello").unwrap();
+    assert!(matches!(network::UdpStack::read(&adapter, &mut socket, &mut [0; 16]), Err(nb::Error::WouldBlock)));
+}
+
+#[test]
+fn socket_timeouts() {
+    let module = MockModule::new();
+    let clock = MockClock::new();
+    let adapter = adapter(&module, &clock);
+    let mut buffer = [0; 8];
+
+    // a blocking socket leaves the waiting to the module, as long as it allows
+    let socket = adapter.open(Mode::Blocking).unwrap();
+    let mut socket = adapter.connect(socket, remote()).unwrap();
+    adapter.write(&mut socket, b"hello").unwrap();
+    assert!(module.sent("S2=20000"));
+    module.receive_data(b"howdy");
+    adapter.read(&mut socket, &mut buffer[..5]).unwrap();
+    assert!(module.sent("R2=20000"));
+    adapter.close(socket).unwrap();
+
+    module.clear_sent();
+    let socket = adapter.open(Mode::NonBlocking).unwrap();
+    let mut socket = adapter.connect(socket, remote()).unwrap();
+    assert!(matches!(adapter.read(&mut socket, &mut buffer), Err(nb::Error::WouldBlock)));
+    assert!(module.sent("R2=1"));
+    adapter.close(socket).unwrap();
+
+    module.clear_sent();
+    let socket = adapter.open(Mode::Timeout(250)).unwrap();
+    let mut socket = adapter.connect(socket, remote()).unwrap();
+    assert!(matches!(adapter.read(&mut socket, &mut buffer), Err(nb::Error::Other(Error::Timeout))));
+    assert!(module.sent("R2=250"));
+}
+
+#[test]
+fn settings_cached() {
+    let module = MockModule::new();
+    let clock = MockClock::new();
+    let mut adapter = adapter(&module, &clock);
+    let mut buffer = [0; 5];
+
+    let socket = adapter.open(Mode::Blocking).unwrap();
+    let mut socket = adapter.connect(socket, remote()).unwrap();
+    adapter.write(&mut socket, b"hello").unwrap();
+    module.receive_data(b"howdy");
+    adapter.read(&mut socket, &mut buffer).unwrap();
+
+    // nothing has changed, so only the transfers themselves are sent
+    module.clear_sent();
+    adapter.write(&mut socket, b"hello").unwrap();
+    module.receive_data(b"howdy");
+    adapter.read(&mut socket, &mut buffer).unwrap();
+    for command in ["P0", "S1", "S2", "R1", "R2", "R3"] {
+        assert!(!module.sent(command), "{} was sent again", command);
+    }
+    assert!(module.sent("S0"));
+    assert!(module.sent("R0"));
+
+    // the backlog is drained once per request, however many transfers it takes
+    module.clear_sent();
+    adapter.write(&mut socket, &[0; 2000]).unwrap();
+    module.receive_data(b"howdy");
+    adapter.read(&mut socket, &mut [0; 8]).unwrap();
+    assert_eq!(module.sent_count("S0"), 2);
+    assert_eq!(module.sent_count("R0"), 1);
+    assert_eq!(module.sent_count("MR"), 2);
+
+    // a read which only partly fills the buffer keeps its read wait
+    let mut large = [0; 64];
+    module.receive_data(b"hey");
+    assert_eq!(adapter.read(&mut socket, &mut large).unwrap(), 3);
+    module.clear_sent();
+    module.receive_data(b"hey");
+    assert_eq!(adapter.read(&mut socket, &mut large).unwrap(), 3);
+    assert!(!module.sent("R2"));
+    assert_eq!(module.sent_count("R0"), 1);
+
+    // another socket's settings may differ
+    let other = adapter.open(Mode::Blocking).unwrap();
+    let _other = adapter.connect(other, remote()).unwrap();
+    module.clear_sent();
+    module.receive_data(b"howdy");
+    adapter.read(&mut socket, &mut buffer).unwrap();
+    assert!(module.sent("P0=0"));
+    assert!(module.sent("R1=5"));
+
+    // and a reset forgets them all
+    adapter.init().unwrap();
+    let socket = adapter.open(Mode::Blocking).unwrap();
+    let mut socket = adapter.connect(socket, remote()).unwrap();
+    module.clear_sent();
+    module.receive_data(b"howdy");
+    adapter.read(&mut socket, &mut buffer).unwrap();
+    assert!(module.sent("R1=5"));
+    assert!(module.sent("R3=1"));
+}
+
+const SCAN: &[u8] = b"\r\n\
+#001,\"drogue\",C4:7F:51:01:02:03,-52,72.0,Infrastructure,WPA2 AES,2.4GHz,6,,\r\n\
+#002,\"guest, 2nd floor\",00:1A:2B:3C:4D:5E,-81,54.0,Infrastructure,Open,2.4GHz,11,,\r\n\
+#003,\"neighbours\",00:1A:2B:3C:4D:5F,-85,54.0,Infrastructure,WPA2 AES,2.4GHz,1,,\r\n\
+#004,\"printer\",00:1A:2B:3C:4D:60,-90,54.0,Infrastructure,WPA WPA2,2.4GHz,11,,\r\n\
+OK\r\n> ";
+
+#[test]
+fn network_status() {
+    let module = MockModule::new();
+    let clock = MockClock::new();
+    let mut adapter = adapter(&module, &clock);
+
+    module.respond("C?", b"\r\ndrogue,rodneygnome,3,1,0,192.168.1.174,255.255.255.0,192.168.1.1,192.168.1.1,0.0.0.0,5,0,0,US,1\r\nOK\r\n> ");
+    module.respond("CR", b"\r\n-79\r\nOK\r\n> ");
+    module.respond("Z5", b"\r\nC4:7F:51:8E:12:0A\r\nOK\r\n> ");
+
+    let status = adapter.network_status().unwrap();
+    assert_eq!(status.ssid.as_str(), "drogue");
+    assert_eq!(status.ip, IpAddr::V4(Ipv4Addr::new(192, 168, 1, 174)));
+    assert_eq!(status.mac, [0xC4, 0x7F, 0x51, 0x8E, 0x12, 0x0A]);
+    assert_eq!(status.rssi, -79);
+    // the status doesn't scan
+    assert!(!module.sent("F0"));
+
+    // of two access points sharing the name, the one heard as the joined one is taken
+    module.respond_once("F0", b"\r\n\
+#001,\"drogue\",C4:7F:51:01:02:03,-52,72.0,Infrastructure,WPA2 AES,2.4GHz,6,,\r\n\
+#002,\"drogue\",C4:7F:51:01:02:04,-80,72.0,Infrastructure,WPA2 AES,2.4GHz,11,,\r\n\
+OK\r\n> ");
+    assert_eq!(adapter.estimate_channel().unwrap(), Some(11));
+
+    module.respond_once("F0", b"\r\nOK\r\n> ");
+    assert_eq!(adapter.estimate_channel().unwrap(), None);
+
+    module.respond_once("F0", b"\r\nScan failed\r\nERROR\r\n> ");
+    assert!(matches!(adapter.estimate_channel(), Err(Error::Module(_))));
+}
+
+#[test]
+fn scan() {
+    let module = MockModule::new();
+    let clock = MockClock::new();
+    let mut adapter = adapter(&module, &clock);
+
+    module.respond("F0", SCAN);
+    let mut results: heapless::Vec<ScanResult, U8> = heapless::Vec::new();
+    adapter.scan(&mut results).unwrap();
+    assert_eq!(results.len(), 4);
+    assert_eq!(results[1].ssid.as_str(), "guest, 2nd floor");
+    assert_eq!(results[3].channel, 11);
+
+    // scanning again replaces the previous results
+    adapter.scan(&mut results).unwrap();
+    assert_eq!(results.len(), 4);
+}
+
+#[test]
+fn scan_truncated() {
+    let module = MockModule::new();
+    let clock = MockClock::new();
+    let mut adapter: Adapter<_, _, _, _, _, _, 4, 256> = Adapter::with_sizes(
+        module.spi(),
+        module.cs(),
+        module.ready(),
+        module.wakeup(),
+        module.reset(),
+        &clock,
+        AdapterConfig::default(),
+    ).unwrap();
+
+    // the response overflows the buffer, but what fit is kept
+    module.respond("F0", SCAN);
+    let mut results: heapless::Vec<ScanResult, U8> = heapless::Vec::new();
+    assert!(matches!(adapter.scan(&mut results), Err(Error::ResponseTruncated)));
+    assert_eq!(results.len(), 3);
+    assert_eq!(results[2].ssid.as_str(), "neighbours");
+}
+
+fn access_point(security: AccessPointSecurity<'_>) -> AccessPointConfig<'_> {
+    AccessPointConfig {
+        ssid: "drogue-setup",
+        security,
+        channel: 6,
+        max_clients: 4,
+        ip: Ipv4Addr::new(192, 168, 10, 1),
+        netmask: Ipv4Addr::new(255, 255, 255, 0),
+        dhcp_start: Ipv4Addr::new(192, 168, 10, 2),
+        dhcp_end: Ipv4Addr::new(192, 168, 10, 20),
+    }
+}
+
+#[test]
+fn start_access_point() {
+    let module = MockModule::new();
+    let clock = MockClock::new();
+    let mut adapter = adapter(&module, &clock);
+
+    adapter.start_access_point(&access_point(AccessPointSecurity::Wpa2 { password: "rodneygnome" })).unwrap();
+    assert!(module.sent_in_order(&[
+        "AS=0,drogue-setup",
+        "A1=3",
+        "A2=rodneygnome",
+        "AC=6",
+        "AT=4",
+        "C6=192.168.10.1",
+        "C7=255.255.255.0",
+        "AL=192.168.10.2,192.168.10.20",
+        "A0",
+    ]));
+
+    module.clear_sent();
+    adapter.start_access_point(&access_point(AccessPointSecurity::Open)).unwrap();
+    assert!(module.sent_in_order(&["AS=0,drogue-setup", "A1=0", "AC=6", "A0"]));
+    assert!(!module.sent("A2="));
+
+    module.clear_sent();
+    adapter.stop_access_point().unwrap();
+    assert!(module.sent("AE"));
+}
+
+#[test]
+fn start_access_point_invalid() {
+    let module = MockModule::new();
+    let clock = MockClock::new();
+    let mut adapter = adapter(&module, &clock);
+    module.clear_sent();
+
+    let config = AccessPointConfig { channel: 14, ..access_point(AccessPointSecurity::Open) };
+    assert!(matches!(adapter.start_access_point(&config), Err(Error::InvalidChannel)));
+    let config = access_point(AccessPointSecurity::WpaMixed { password: "short" });
+    assert!(matches!(adapter.start_access_point(&config), Err(Error::InvalidPassword)));
+    assert!(!module.sent("AS"));
+
+    module.respond("A0", b"\r\nERROR\r\n> ");
+    assert!(matches!(adapter.start_access_point(&access_point(AccessPointSecurity::Open)), Err(Error::Module(_))));
+}
+
+#[test]
+fn access_point_stations() {
+    let module = MockModule::new();
+    let clock = MockClock::new();
+    let mut adapter = adapter(&module, &clock);
+    module.respond(
+        "AR",
+        b"\r\n#001,C4:7F:51:01:02:03,192.168.10.2\r\n#002,00:1A:2B:3C:4D:5E,192.168.10.3\r\nOK\r\n> ",
+    );
+
+    let mut stations = heapless::Vec::<Station, U8>::new();
+    adapter.access_point_stations(&mut stations).unwrap();
+    assert_eq!(stations.len(), 2);
+    assert_eq!(stations[0].mac, [0xC4, 0x7F, 0x51, 0x01, 0x02, 0x03]);
+    assert_eq!(stations[0].ip, IpAddr::V4(Ipv4Addr::new(192, 168, 10, 2)));
+    assert_eq!(stations[1].mac, [0x00, 0x1A, 0x2B, 0x3C, 0x4D, 0x5E]);
+    assert_eq!(stations[1].ip, IpAddr::V4(Ipv4Addr::new(192, 168, 10, 3)));
+
+    // an empty list, and a list longer than the caller's vector
+    module.respond_once("AR", b"\r\nOK\r\n> ");
+    adapter.access_point_stations(&mut stations).unwrap();
+    assert!(stations.is_empty());
+
+    let mut one = heapless::Vec::<Station, heapless::consts::U1>::new();
+    adapter.access_point_stations(&mut one).unwrap();
+    assert_eq!(one[0].ip, IpAddr::V4(Ipv4Addr::new(192, 168, 10, 2)));
+}
+
+#[test]
+fn small_adapter() {
+    let module = MockModule::new();
+    let clock = MockClock::new();
+    let mut adapter: Adapter<_, _, _, _, _, _, 2, 256> = Adapter::with_sizes(
+        module.spi(),
+        module.cs(),
+        module.ready(),
+        module.wakeup(),
+        module.reset(),
+        &clock,
+        AdapterConfig::default(),
+    ).unwrap();
+    adapter.init().unwrap();
+
+    let first = adapter.open(Mode::NonBlocking).unwrap();
+    let _second = adapter.open(Mode::NonBlocking).unwrap();
+    assert!(matches!(adapter.open(Mode::NonBlocking), Err(Error::NoAvailableSockets)));
+
+    // socket data is received straight into the caller's buffer, however small the adapter's
+    let data: Vec<u8> = (0..600).map(|i| i as u8).collect();
+    module.receive_data(&data);
+    let mut socket = adapter.connect(first, remote()).unwrap();
+    let mut buffer = [0; 600];
+    assert_eq!(adapter.read(&mut socket, &mut buffer).unwrap(), 600);
+    assert_eq!(&buffer[..], &data[..]);
+    assert!(module.sent("R1=600"));
+}
+
+proptest! {
+    #[test]
+    fn read_round_trip(data in proptest::collection::vec(any::<u8>(), 1..3000)) {
+        let module = MockModule::new();
+        let clock = MockClock::new();
+        let adapter = adapter(&module, &clock);
+
+        let socket = adapter.open(Mode::NonBlocking).unwrap();
+        let mut socket = adapter.connect(socket, remote()).unwrap();
+
+        module.receive_data(&data);
+        // each read returns what a single module read delivers
+        let mut buffer = vec![0; data.len()];
+        let mut pos = 0;
+        while pos < buffer.len() {
+            let len = adapter.read(&mut socket, &mut buffer[pos..]).unwrap();
+            prop_assert!(len > 0);
+            pos += len;
+        }
+        prop_assert_eq!(buffer, data);
+    }
+}